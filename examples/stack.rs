@@ -0,0 +1,15 @@
+use math_evaluator::evaluator::eval;
+use math_evaluator::functions::Arity;
+use math_evaluator::state::State;
+fn main() {
+    let mut state: State<i32> = State::new();
+    state.register_function("sp", Arity::Exact(0), |_| { let x = 0u8; Ok((&x as *const u8 as usize / 16) as i32) });
+    let base = eval("sp()".chars(), &mut state).unwrap() as i64;
+    let n = 100;
+    for (open, close, name) in [("(", ")", "paren"), ("1 * (", ")", "binary"), ("-(", ")", "unary"), ("(1 ? ", " : 0)", "cond"), ("abs(", ")", "builtin"), ("id(", ")", "userfn")] {
+        eval("id(x) = x".chars(), &mut state).unwrap();
+        let e = format!("{}sp(){}", open.repeat(n), close.repeat(n));
+        let v = eval(e.chars(), &mut state).unwrap() as i64;
+        println!("{}: {} bytes per level", name, (base - v) * 16 / n as i64);
+    }
+}
@@ -0,0 +1,238 @@
+use std::collections::{HashMap, HashSet};
+use std::fmt::{Debug, Formatter};
+use crate::error::{Error, SyntaxError};
+use crate::evaluator::parse;
+use crate::expression::Expression;
+use crate::node::Node;
+use crate::span::Span;
+use crate::state::State;
+use crate::value::Value;
+
+/// A store of live formulas, `total = price * qty`, that are recomputed whenever a variable they depend on changes.
+///
+/// Every change is atomic: if the formula or any formula depending on it fails,
+/// neither the variables nor the formulas of the sheet change.
+#[derive(Clone)]
+pub struct Sheet<V = i32> {
+    state: State<V>,
+    cells: HashMap<String, Cell<V>>,
+}
+
+#[derive(Clone)]
+struct Cell<V> {
+    formula: Expression<V>,
+    /// Variables the formula reads, with the part of the formula that reads them
+    dependencies: Vec<(String, Span)>,
+}
+
+impl<V: Value> Sheet<V> {
+    pub fn new() -> Self {
+        Self::with_state(State::new())
+    }
+
+    /// A sheet whose variables are kept in the given state, e.g. one with functions registered by the host
+    pub fn with_state(state: State<V>) -> Self {
+        Sheet { state, cells: HashMap::new() }
+    }
+
+    pub fn state(&self) -> &State<V> {
+        &self.state
+    }
+
+    /// Current value of the variable or constant
+    pub fn get(&self, name: &str) -> Option<V> {
+        self.state.get(name)
+    }
+
+    /// The formula of the variable, `None` if it is set to a plain value or not at all
+    pub fn formula(&self, name: &str) -> Option<&Expression<V>> {
+        self.cells.get(name).map(|cell| &cell.formula)
+    }
+
+    /// Names of the variables the formula of the variable reads
+    pub fn dependencies(&self, name: &str) -> Vec<&str> {
+        self.cells.get(name).map_or_else(Vec::new, |cell| cell.dependencies.iter().map(|(name, _)| name.as_str()).collect())
+    }
+
+    /// Defines the variable by a formula such as `total = price * qty`, replacing its previous value or formula,
+    /// and recomputes everything depending on it. Results in the new value of the variable.
+    /// A formula must not change variables, so assignments, `++` and `--` are rejected,
+    /// also inside the functions it calls.
+    pub fn set(&mut self, formula: &str) -> Result<V, Error> {
+        let formula = parse::<V>(formula)?;
+        let (name, value) = match &formula.root {
+            Node::Binary { left, right, sign: "=", .. } => match left.unparenthesized() {
+                Node::Variable { name, .. } => (name.clone(), right),
+                _ => return Err(Error::InvalidSyntax(SyntaxError::InvalidFormula, formula.root.span())),
+            },
+            root => return Err(Error::InvalidSyntax(SyntaxError::InvalidFormula, root.span())),
+        };
+        if let Some(span) = find_assignment(value, &self.state, None, &mut HashSet::new()) {
+            return Err(Error::InvalidSyntax(SyntaxError::AssignmentInFormula, span));
+        }
+        let mut dependencies = Vec::new();
+        collect_dependencies(value, &self.state, &[], None, &mut HashSet::new(), &mut dependencies);
+        if let Some((cycle, span)) = self.find_cycle(&name, &dependencies) {
+            return Err(Error::CyclicFormula(cycle, span));
+        }
+
+        let previous = self.cells.insert(name.clone(), Cell { formula, dependencies });
+        let cells = &self.cells;
+        let result = self.state.transaction(|state| {
+            let value = cells[&name].formula.eval(state)?;
+            recompute(cells, &name, state)?;
+            Ok(value)
+        });
+        if result.is_err() {
+            match previous {
+                Some(previous) => self.cells.insert(name, previous),
+                None => self.cells.remove(&name),
+            };
+        }
+        result
+    }
+
+    /// Sets the variable to a plain value, replacing its formula, and recomputes everything depending on it.
+    /// The span of `Error::AssignToConstant` refers to the name, the spans of errors in recomputed formulas
+    /// refer to the source of the failing formula.
+    pub fn set_value(&mut self, name: &str, value: V) -> Result<(), Error> {
+        if self.state.constants.contains_key(name) {
+            return Err(Error::AssignToConstant(name.to_string(), Span::new(0, name.len())));
+        }
+        let previous = self.cells.remove(name);
+        let result = self.state.transaction(|state| {
+            state.set(name, value);
+            recompute(&self.cells, name, state)
+        });
+        if let (Err(_), Some(previous)) = (&result, previous) {
+            self.cells.insert(name.to_string(), previous);
+        }
+        result
+    }
+
+    /// Path of names from the variable through the formulas back to it, if the dependencies lead to it
+    fn find_cycle(&self, name: &str, dependencies: &[(String, Span)]) -> Option<(Vec<String>, Span)> {
+        let mut visited = HashSet::new();
+        dependencies.iter().find_map(|(dependency, span)| {
+            let mut cycle = self.find_path(dependency, name, &mut visited)?;
+            cycle.insert(0, name.to_string());
+            Some((cycle, *span))
+        })
+    }
+
+    fn find_path(&self, from: &str, to: &str, visited: &mut HashSet<String>) -> Option<Vec<String>> {
+        if from == to {
+            return Some(vec![to.to_string()]);
+        }
+        if !visited.insert(from.to_string()) {
+            return None;
+        }
+        self.cells.get(from)?.dependencies.iter().find_map(|(dependency, _)| {
+            let mut path = self.find_path(dependency, to, visited)?;
+            path.insert(0, from.to_string());
+            Some(path)
+        })
+    }
+}
+
+impl<V: Value> Debug for Sheet<V> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let formulas: HashMap<&str, &Expression<V>> = self.cells.iter().map(|(name, cell)| (name.as_str(), &cell.formula)).collect();
+        f.debug_struct("Sheet").field("state", &self.state).field("formulas", &formulas).finish()
+    }
+}
+
+impl<V: Value> Default for Sheet<V> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Evaluates every formula depending on the changed variable after all formulas it depends on
+fn recompute<V: Value>(cells: &HashMap<String, Cell<V>>, changed: &str, state: &mut State<V>) -> Result<(), Error> {
+    let mut order = Vec::new();
+    dependents_after(cells, changed, &mut HashSet::new(), &mut order);
+    // the changed variable itself comes first
+    for name in order.iter().rev().skip(1) {
+        cells[name].formula.eval(state)?;
+    }
+    Ok(())
+}
+
+/// Pushes the variable after everything depending on it, reversing the order sorts the formulas topologically
+fn dependents_after<V>(cells: &HashMap<String, Cell<V>>, name: &str, visited: &mut HashSet<String>, order: &mut Vec<String>) {
+    if !visited.insert(name.to_string()) {
+        return;
+    }
+    for (dependent, cell) in cells {
+        if cell.dependencies.iter().any(|(dependency, _)| dependency == name) {
+            dependents_after(cells, dependent, visited, order);
+        }
+    }
+    order.push(name.to_string());
+}
+
+/// Collects the free variables the node reads, including the ones read by the functions it calls.
+/// Variables read inside a function are attributed to the span of the call.
+fn collect_dependencies<V: Value>(node: &Node<V>, state: &State<V>, bound: &[String], call: Option<Span>, called: &mut HashSet<String>, found: &mut Vec<(String, Span)>) {
+    let mut collect = |node: &Node<V>, found: &mut Vec<(String, Span)>| collect_dependencies(node, state, bound, call, called, found);
+    match node {
+        Node::Variable { name, span } => {
+            if !bound.contains(name) && !found.iter().any(|(found, _)| found == name) {
+                found.push((name.clone(), call.unwrap_or(*span)));
+            }
+        }
+        Node::Constant { .. } => {}
+        Node::Parenthesis { child, .. } | Node::Unary { child, .. } => collect(child, found),
+        // defining a function reads nothing
+        Node::Binary { left, sign: "=", .. } if matches!(left.unparenthesized(), Node::Call { .. }) => {}
+        Node::Binary { left, right, sign: "=", .. } if matches!(left.unparenthesized(), Node::Variable { .. }) => {
+            collect(right, found);
+        }
+        Node::Binary { left, right, .. } => {
+            collect(left, found);
+            collect(right, found);
+        }
+        Node::Call { name, arguments, span } => {
+            for argument in arguments {
+                collect(argument, found);
+            }
+            if let Some(function) = state.definitions.get(name) {
+                if called.insert(name.clone()) {
+                    collect_dependencies(&function.body, state, &function.parameters, Some(call.unwrap_or(*span)), called, found);
+                }
+            }
+        }
+        Node::Declaration { value, .. } => collect(value, found),
+        Node::Conditional { condition, then, otherwise, .. } => {
+            collect(condition, found);
+            collect(then, found);
+            collect(otherwise, found);
+        }
+    }
+}
+
+/// Span of the first assignment, compound assignment, increment, decrement or declaration in the node,
+/// including the ones in the functions it calls. Those inside a function are attributed to the span of the call.
+fn find_assignment<V: Value>(node: &Node<V>, state: &State<V>, call: Option<Span>, called: &mut HashSet<String>) -> Option<Span> {
+    let mut find = |node: &Node<V>| find_assignment(node, state, call, called);
+    match node {
+        Node::Variable { .. } | Node::Constant { .. } => None,
+        Node::Unary { sign: "++" | "--", span, .. }
+        | Node::Binary { sign: "=" | "+=" | "-=" | "*=" | "/=", span, .. }
+        | Node::Declaration { span, .. } => Some(call.unwrap_or(*span)),
+        Node::Parenthesis { child, .. } | Node::Unary { child, .. } => find(child),
+        Node::Binary { left, right, .. } => find(left).or_else(|| find(right)),
+        Node::Call { name, arguments, span } => {
+            if let Some(span) = arguments.iter().find_map(&mut find) {
+                return Some(span);
+            }
+            let function = state.definitions.get(name)?;
+            if !called.insert(name.clone()) {
+                return None;
+            }
+            find_assignment(&function.body, state, Some(call.unwrap_or(*span)), called)
+        }
+        Node::Conditional { condition, then, otherwise, .. } => find(condition).or_else(|| find(then)).or_else(|| find(otherwise)),
+    }
+}
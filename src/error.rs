@@ -1,26 +1,325 @@
 use std::fmt::{Debug, Display, Formatter};
+use crate::functions::Arity;
+use crate::span::Span;
 
 #[derive(Debug, PartialEq)]
 pub enum Error {
-    UninitializedVariable(String),
-    InvalidSyntax,
-    CanOnlyAssignToVariable,
+    UninitializedVariable(String, Span),
+    InvalidSyntax(SyntaxError, Span),
+    CanOnlyAssignToVariable(Span),
+    AssignToConstant(String, Span),
+    DivisionByZero(Span),
+    /// The result of `lhs op rhs` (or `op rhs` for unary operations) does not fit into the value type
+    Overflow {
+        op: String,
+        lhs: Option<String>,
+        rhs: String,
+        span: Span,
+    },
+    /// The operation is not defined for the operand, e.g. the factorial of a negative number
+    InvalidOperand {
+        op: String,
+        operand: String,
+        span: Span,
+    },
+    UnknownFunction(String, Span),
+    /// The function was called with a number of arguments it does not accept
+    ArityMismatch {
+        name: String,
+        expected: Arity,
+        found: usize,
+        span: Span,
+    },
+    /// A parameter in a function definition is not a variable name
+    InvalidParameter(Span),
+    DuplicateParameter(String, Span),
+    /// Calls of functions defined inside expressions are nested deeper than `State::max_call_depth`
+    RecursionLimit(String, Span),
+    /// The evaluation nests deeper than `State::max_nesting`
+    NestingLimit(Span),
+    /// A formula of a `cells::Sheet` depends on itself, the names form the cycle from and back to the formula
+    CyclicFormula(Vec<String>, Span),
+    /// A function registered by the host application failed
+    Host {
+        function: String,
+        error: HostError,
+        span: Span,
+    },
+}
+
+/// Error of a statement in a program
+#[derive(Debug, PartialEq)]
+pub struct ProgramError {
+    /// Number of the statement that failed, starting at 1
+    pub statement: usize,
+    pub error: Error,
+}
+
+impl ProgramError {
+    /// Renders the error like `Error::render`, the span refers to the source of the whole program
+    pub fn render(&self, source: &str) -> String {
+        format!("Statement {}: {}", self.statement, self.error.render(source))
+    }
+}
+
+impl Display for ProgramError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Statement {}: {}", self.statement, self.error)
+    }
+}
+
+impl std::error::Error for ProgramError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        Some(&self.error)
+    }
+}
+
+/// Error of `State::load`, the state is left unchanged
+#[derive(Debug, PartialEq)]
+pub enum LoadError {
+    /// The text is not a saved state
+    Format(String),
+    /// The state was saved in a newer version of the format
+    UnsupportedVersion(u32),
+    /// A saved value cannot be read as the value type, e.g. a fraction loaded in integer mode
+    InvalidValue {
+        name: String,
+        value: String,
+    },
+    /// The body or the parameters of a saved function are invalid
+    InvalidFunction {
+        name: String,
+        error: Error,
+    },
+}
+
+impl Display for LoadError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            LoadError::Format(reason) => write!(f, "Not a saved state: {}", reason),
+            LoadError::UnsupportedVersion(version) => write!(f, "Unsupported version of the saved state: {}", version),
+            LoadError::InvalidValue { name, value } => write!(f, "Invalid value of {}: {}", name, value),
+            LoadError::InvalidFunction { name, error } => write!(f, "Invalid function {}: {}", name, error),
+        }
+    }
+}
+
+impl std::error::Error for LoadError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            LoadError::InvalidFunction { error, .. } => Some(error),
+            _ => None,
+        }
+    }
+}
+
+/// Error returned by a function registered with `State::register_function`
+#[derive(Debug)]
+pub struct HostError(Box<dyn std::error::Error + Send + Sync>);
+
+impl HostError {
+    /// Wraps any error, or a message given as a string
+    pub fn new(error: impl Into<Box<dyn std::error::Error + Send + Sync>>) -> Self {
+        HostError(error.into())
+    }
+
+    /// The error as returned by the host function
+    pub fn get_ref(&self) -> &(dyn std::error::Error + Send + Sync + 'static) {
+        &*self.0
+    }
+}
+
+/// Host errors are compared by their message, the underlying errors do not need to be comparable
+impl PartialEq for HostError {
+    fn eq(&self, other: &Self) -> bool {
+        self.0.to_string() == other.0.to_string()
+    }
+}
+
+impl Display for HostError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        Display::fmt(&self.0, f)
+    }
+}
+
+/// The reason why an expression could not be parsed
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum SyntaxError {
+    UnexpectedToken,
+    MissingOperand,
+    UnbalancedParenthesis,
+    InvalidNumber,
+    /// A `?` without the `:` separating the branches of the conditional
+    MissingColon,
+    /// A `/*` comment that is never closed
+    UnterminatedComment,
+    /// `const` that is not followed by an assignment to a name
+    InvalidDeclaration,
+    /// A formula of a `cells::Sheet` that is not an assignment to a name
+    InvalidFormula,
+    /// A formula of a `cells::Sheet` that changes a variable, e.g. `total = qty++`
+    AssignmentInFormula,
+}
+
+impl Error {
+    /// Part of the source the error refers to
+    pub fn span(&self) -> Span {
+        match self {
+            Error::UninitializedVariable(_, span)
+            | Error::InvalidSyntax(_, span)
+            | Error::CanOnlyAssignToVariable(span)
+            | Error::AssignToConstant(_, span)
+            | Error::DivisionByZero(span)
+            | Error::Overflow { span, .. }
+            | Error::InvalidOperand { span, .. }
+            | Error::UnknownFunction(_, span)
+            | Error::ArityMismatch { span, .. }
+            | Error::InvalidParameter(span)
+            | Error::DuplicateParameter(_, span)
+            | Error::RecursionLimit(_, span)
+            | Error::NestingLimit(span)
+            | Error::CyclicFormula(_, span)
+            | Error::Host { span, .. } => *span,
+        }
+    }
+
+    /// The same error pointing at another part of the source
+    pub(crate) fn with_span(mut self, to: Span) -> Self {
+        match &mut self {
+            Error::UninitializedVariable(_, span)
+            | Error::InvalidSyntax(_, span)
+            | Error::CanOnlyAssignToVariable(span)
+            | Error::AssignToConstant(_, span)
+            | Error::DivisionByZero(span)
+            | Error::Overflow { span, .. }
+            | Error::InvalidOperand { span, .. }
+            | Error::UnknownFunction(_, span)
+            | Error::ArityMismatch { span, .. }
+            | Error::InvalidParameter(span)
+            | Error::DuplicateParameter(_, span)
+            | Error::RecursionLimit(_, span)
+            | Error::NestingLimit(span)
+            | Error::CyclicFormula(_, span)
+            | Error::Host { span, .. } => *span = to,
+        }
+        self
+    }
+
+    /// Renders the error message followed by the offending source line
+    /// with the erroneous part underlined by carets
+    pub fn render(&self, source: &str) -> String {
+        let span = self.span();
+        let start = floor_char_boundary(source, span.start);
+        let end = floor_char_boundary(source, span.end.max(start));
+
+        let line_start = source[..start].rfind('\n').map_or(0, |i| i + 1);
+        let line_end = source[start..].find('\n').map_or(source.len(), |i| start + i);
+        let line_number = source[..line_start].matches('\n').count() + 1;
+        let line = &source[line_start..line_end];
+
+        let column = source[line_start..start].chars().count();
+        let width = source[start..end.min(line_end)].chars().count().max(1);
+
+        let gutter = line_number.to_string();
+        let padding = " ".repeat(gutter.len());
+        format!(
+            "{}\n{} | {}\n{} | {}{}",
+            self,
+            gutter,
+            line,
+            padding,
+            " ".repeat(column),
+            "^".repeat(width),
+        )
+    }
+}
+
+/// The largest char boundary of `source` not after `index`, so that spans which do not belong to the source
+/// still render without panicking
+fn floor_char_boundary(source: &str, index: usize) -> usize {
+    let mut index = index.min(source.len());
+    while !source.is_char_boundary(index) {
+        index -= 1;
+    }
+    index
+}
+
+impl Display for SyntaxError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            SyntaxError::UnexpectedToken => write!(f, "unexpected token"),
+            SyntaxError::MissingOperand => write!(f, "missing operand"),
+            SyntaxError::UnbalancedParenthesis => write!(f, "unbalanced parenthesis"),
+            SyntaxError::InvalidNumber => write!(f, "invalid number literal"),
+            SyntaxError::MissingColon => write!(f, "missing ':' of the conditional"),
+            SyntaxError::UnterminatedComment => write!(f, "unterminated comment"),
+            SyntaxError::InvalidDeclaration => write!(f, "expected `const name = value`"),
+            SyntaxError::InvalidFormula => write!(f, "expected `name = formula`"),
+            SyntaxError::AssignmentInFormula => write!(f, "a formula cannot change variables"),
+        }
+    }
 }
 
 impl Display for Error {
     fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
         match &self {
-            Error::UninitializedVariable(name) => {
+            Error::UninitializedVariable(name, _) => {
                 write!(f, "Uninitialized variable: {}", name)
             }
-            Error::InvalidSyntax => {
-                write!(f, "Invalid syntax")
+            Error::InvalidSyntax(reason, _) => {
+                write!(f, "Invalid syntax: {}", reason)
             }
-            Error::CanOnlyAssignToVariable => {
+            Error::CanOnlyAssignToVariable(_) => {
                 write!(f, "Can only assign to variable")
             }
+            Error::AssignToConstant(name, _) => {
+                write!(f, "Cannot assign to constant: {}", name)
+            }
+            Error::DivisionByZero(_) => {
+                write!(f, "Division by zero")
+            }
+            Error::Overflow { op, lhs: Some(lhs), rhs, .. } => {
+                write!(f, "Overflow: {} {} {}", lhs, op, rhs)
+            }
+            Error::Overflow { op, lhs: None, rhs, .. } => {
+                write!(f, "Overflow: {} applied to {}", op, rhs)
+            }
+            Error::InvalidOperand { op, operand, .. } => {
+                write!(f, "Invalid operand for {}: {}", op, operand)
+            }
+            Error::UnknownFunction(name, _) => {
+                write!(f, "Unknown function: {}", name)
+            }
+            Error::ArityMismatch { name, expected, found, .. } => {
+                write!(f, "Function {} expects {} argument(s), found {}", name, expected, found)
+            }
+            Error::InvalidParameter(_) => {
+                write!(f, "Function parameters must be variable names")
+            }
+            Error::DuplicateParameter(name, _) => {
+                write!(f, "Duplicate parameter: {}", name)
+            }
+            Error::RecursionLimit(name, _) => {
+                write!(f, "Recursion limit exceeded in {}", name)
+            }
+            Error::NestingLimit(_) => {
+                write!(f, "Expression nested too deeply")
+            }
+            Error::CyclicFormula(cycle, _) => {
+                write!(f, "Cyclic formula: {}", cycle.join(" -> "))
+            }
+            Error::Host { function, error, .. } => {
+                write!(f, "Error in {}: {}", function, error)
+            }
         }
     }
 }
 
-impl std::error::Error for Error {}
\ No newline at end of file
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Host { error, .. } => Some(error.get_ref()),
+            _ => None,
+        }
+    }
+}
@@ -1,8 +1,13 @@
-use std::cmp::Ordering;
-use std::ops::{Deref};
-use crate::error::Error;
+use crate::error::{Error, SyntaxError};
+use std::iter::Peekable;
+use crate::error::ProgramError;
+use crate::expression::Expression;
+use crate::functions;
 use crate::node::Node;
-use crate::state::State;
+use crate::program::Program;
+use crate::span::Span;
+use crate::state::{OverflowPolicy, RemainderMode, State};
+use crate::value::{ArithmeticError, Value};
 use crate::token::{OperandsToken, OperationToken, Token, TokenIterator};
 
 
@@ -14,231 +19,873 @@ enum Operation {
     BinaryMinus,
     Multiply,
     Divide,
+    FloorDivide,
+    Remainder,
+    Power,
+    Factorial,
+    Not,
+    Equal,
+    NotEqual,
+    Less,
+    LessEqual,
+    Greater,
+    GreaterEqual,
+    And,
+    Or,
+    BitAnd,
+    BitOr,
+    Xor,
+    BitNot,
+    SquareRoot,
+    ShiftLeft,
+    ShiftRight,
+    /// The `?` of a conditional whose `:` has not been reached yet
+    Question,
+    /// The `:` of a conditional, collapses into the conditional itself
+    Conditional,
     OpenParenthesis,
-    CloseParenthesis,
+    /// Marks the start of the argument list of a function call
+    Call,
     Assign,
+    PlusAssign,
+    MinusAssign,
+    MultiplyAssign,
+    DivideAssign,
+    PreIncrement,
+    PreDecrement,
+    PostIncrement,
+    PostDecrement,
 }
 
-impl PartialOrd for Operation {
-    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
-        match (self, other) {
-            (Operation::UnaryPlus | Operation::BinaryPlus | Operation::UnaryMinus | Operation::BinaryMinus, Operation::UnaryPlus | Operation::BinaryPlus | Operation::UnaryMinus | Operation::BinaryMinus) => Some(Ordering::Equal),
-            (Operation::Multiply | Operation::Divide, Operation::Multiply | Operation::Divide) => Some(Ordering::Equal),
-            (Operation::UnaryPlus | Operation::BinaryPlus | Operation::UnaryMinus | Operation::BinaryMinus, Operation::Multiply | Operation::Divide) => Some(Ordering::Greater),
-            (Operation::Multiply | Operation::Divide, Operation::UnaryPlus | Operation::BinaryPlus | Operation::UnaryMinus | Operation::BinaryMinus) => Some(Ordering::Less),
-            (Operation::OpenParenthesis, _) => Some(Ordering::Greater),
-            (Operation::CloseParenthesis, _) => Some(Ordering::Less),
-            (_, Operation::OpenParenthesis) => Some(Ordering::Greater),
-            (_, Operation::CloseParenthesis) => Some(Ordering::Less),
-            (Operation::Assign, _) => Some(Ordering::Greater),
-            (_, Operation::Assign) => Some(Ordering::Greater)
+impl Operation {
+    /// How strongly the operation binds the operand on its left and on its right.
+    ///
+    /// An operation waiting on the stack is collapsed before a new one is pushed
+    /// if it binds its right operand at least as strongly as the new one binds its left operand.
+    /// Left-associative operations bind slightly stronger to the right, right-associative ones to the left.
+    /// Prefix operations have no left operand, so they never collapse anything that is already on the stack,
+    /// and assignment always takes the operand immediately to its left.
+    fn binding_power(&self) -> (u8, u8) {
+        match self {
+            Operation::OpenParenthesis | Operation::Call => (u8::MAX, 0),
+            Operation::Assign | Operation::PlusAssign | Operation::MinusAssign | Operation::MultiplyAssign | Operation::DivideAssign => (u8::MAX, 1),
+            Operation::Question => (3, 0),
+            Operation::Conditional => (u8::MAX, 2),
+            Operation::Or => (4, 5),
+            Operation::And => (6, 7),
+            Operation::BitOr => (8, 9),
+            Operation::Xor => (10, 11),
+            Operation::BitAnd => (12, 13),
+            Operation::Equal | Operation::NotEqual => (14, 15),
+            Operation::Less | Operation::LessEqual | Operation::Greater | Operation::GreaterEqual => (16, 17),
+            Operation::ShiftLeft | Operation::ShiftRight => (18, 19),
+            Operation::BinaryPlus | Operation::BinaryMinus => (20, 21),
+            Operation::Multiply | Operation::Divide | Operation::FloorDivide | Operation::Remainder => (30, 31),
+            Operation::UnaryPlus | Operation::UnaryMinus | Operation::Not | Operation::BitNot | Operation::SquareRoot | Operation::PreIncrement | Operation::PreDecrement => (u8::MAX, 40),
+            Operation::Power => (51, 50),
+            Operation::Factorial | Operation::PostIncrement | Operation::PostDecrement => (u8::MAX, u8::MAX),
         }
     }
+
+    /// Whether `self`, waiting on the stack, has to be collapsed before `incoming` is pushed
+    fn collapses_before(&self, incoming: &Operation) -> bool {
+        self.binding_power().1 >= incoming.binding_power().0
+    }
+}
+
+/// Turns the failure of `lhs op rhs` (or `op rhs` for unary operations) into an evaluation error
+fn arithmetic_error<V: Value>(error: ArithmeticError, op: &str, lhs: Option<&V>, rhs: &V, span: Span, rhs_span: Span) -> Error {
+    match error {
+        ArithmeticError::Overflow => Error::Overflow { op: op.to_string(), lhs: lhs.map(V::to_string), rhs: rhs.to_string(), span },
+        ArithmeticError::DivisionByZero => Error::DivisionByZero(rhs_span),
+        ArithmeticError::InvalidOperand => Error::InvalidOperand { op: op.to_string(), operand: rhs.to_string(), span: rhs_span },
+    }
+}
+
+/// Evaluates the child and applies the operation according to the overflow policy
+fn unary_arithmetic<V: Value>(sign: &str, operation: fn(&V, OverflowPolicy) -> Result<V, ArithmeticError>, child: &Node<V>, state: &mut State<V>) -> Result<V, Error> {
+    let value = child.eval(state)?;
+    operation(&value, state.overflow)
+        .map_err(|e| arithmetic_error(e, sign, None, &value, child.span(), child.span()))
+}
+
+/// Evaluates both operands and applies the operation according to the overflow policy
+fn binary_arithmetic<V: Value>(sign: &str, operation: fn(&V, &V, OverflowPolicy) -> Result<V, ArithmeticError>, left: &Node<V>, right: &Node<V>, state: &mut State<V>) -> Result<V, Error> {
+    let l = left.eval(state)?;
+    let r = right.eval(state)?;
+    operation(&l, &r, state.overflow)
+        .map_err(|e| arithmetic_error(e, sign, Some(&l), &r, left.span().join(right.span()), right.span()))
+}
+
+/// Name of the variable an assignment writes to, constants cannot be assigned to
+fn assignment_target<'a, V: Value>(target: &'a Node<V>, state: &State<V>) -> Result<&'a str, Error> {
+    match target.unparenthesized() {
+        Node::Variable { name, span } if state.is_constant(name) => Err(Error::AssignToConstant(name.clone(), *span)),
+        Node::Variable { name, .. } => Ok(name.as_str()),
+        _ => Err(Error::CanOnlyAssignToVariable(target.span())),
+    }
+}
+
+/// Evaluates the right operand, combines the current value of the target with it and stores the result.
+/// The target is read exactly once.
+fn compound_assignment<V: Value>(sign: &str, operation: fn(&V, &V, OverflowPolicy) -> Result<V, ArithmeticError>, left: &Node<V>, right: &Node<V>, state: &mut State<V>) -> Result<V, Error> {
+    let name = assignment_target(left, state)?;
+    let r = right.eval(state)?; // eval right before left
+    let l = left.eval(state)?;
+    let result = operation(&l, &r, state.overflow)
+        .map_err(|e| arithmetic_error(e, sign, Some(&l), &r, left.span().join(right.span()), right.span()))?;
+    state.set(name, result.clone());
+    Ok(result)
+}
+
+/// Adds or subtracts one from the target, resulting in the new value for prefix and the old one for postfix operations
+fn step<V: Value>(sign: &str, operation: fn(&V, &V, OverflowPolicy) -> Result<V, ArithmeticError>, postfix: bool, target: &Node<V>, state: &mut State<V>) -> Result<V, Error> {
+    let name = assignment_target(target, state)?;
+    let old = target.eval(state)?;
+    let new = operation(&old, &V::from_i32(1), state.overflow)
+        .map_err(|e| arithmetic_error(e, sign, None, &old, target.span(), target.span()))?;
+    state.set(name, new.clone());
+    Ok(if postfix { old } else { new })
+}
+
+/// Evaluates both operands and compares them, resulting in `1` or `0`
+fn comparison<V: Value>(test: fn(&V, &V) -> bool, left: &Node<V>, right: &Node<V>, state: &mut State<V>) -> Result<V, Error> {
+    let l = left.eval(state)?;
+    let r = right.eval(state)?;
+    Ok(V::from_bool(test(&l, &r)))
 }
 
 /// Pop one/two node(s) from the node list and add put new operation node inside
-fn collapse(operation: Operation, nodes: &mut Vec<Node>) -> Result<(), Error> {
+fn collapse<V: Value>(operation: Operation, span: Span, nodes: &mut Vec<Node<V>>) -> Result<(), Error> {
+    let missing_operand = Error::InvalidSyntax(SyntaxError::MissingOperand, span);
     match operation {
         Operation::UnaryPlus => {
-            let prev = nodes.pop().ok_or(Error::InvalidSyntax)?;
+            let prev = nodes.pop().ok_or(missing_operand)?;
             nodes.push(
                 Node::Unary {
+                    span: span.join(prev.span()),
                     child: Box::new(prev),
-                    sign: '+',
-                    strategy: |child, state| Ok(child.eval(state)?),
+                    sign: "+",
+                    postfix: false,
+                    strategy: |child, state| child.eval(state),
                 }
             )
         }
         Operation::UnaryMinus => {
-            let prev = nodes.pop().ok_or(Error::InvalidSyntax)?;
+            let prev = nodes.pop().ok_or(missing_operand)?;
+            nodes.push(
+                Node::Unary {
+                    span: span.join(prev.span()),
+                    child: Box::new(prev),
+                    sign: "-",
+                    postfix: false,
+                    strategy: |child, state| unary_arithmetic("-", V::negate, child, state),
+                }
+            );
+        }
+        Operation::Not => {
+            let prev = nodes.pop().ok_or(missing_operand)?;
             nodes.push(
                 Node::Unary {
+                    span: span.join(prev.span()),
                     child: Box::new(prev),
-                    sign: '-',
-                    strategy: |child, state| Ok(-child.eval(state)?),
+                    sign: "!",
+                    postfix: false,
+                    strategy: |child, state| Ok(V::from_bool(!child.eval(state)?.is_truthy())),
+                }
+            );
+        }
+        Operation::BitNot => {
+            let prev = nodes.pop().ok_or(missing_operand)?;
+            nodes.push(
+                Node::Unary {
+                    span: span.join(prev.span()),
+                    child: Box::new(prev),
+                    sign: "~",
+                    postfix: false,
+                    strategy: |child, state| unary_arithmetic("~", V::bit_not, child, state),
+                }
+            );
+        }
+        Operation::SquareRoot => {
+            let prev = nodes.pop().ok_or(missing_operand)?;
+            nodes.push(
+                Node::Unary {
+                    span: span.join(prev.span()),
+                    child: Box::new(prev),
+                    sign: "√",
+                    postfix: false,
+                    strategy: |child, state| unary_arithmetic("√", |value, _| value.sqrt(), child, state),
+                }
+            );
+        }
+        Operation::Factorial => {
+            let prev = nodes.pop().ok_or(missing_operand)?;
+            nodes.push(
+                Node::Unary {
+                    span: prev.span().join(span),
+                    child: Box::new(prev),
+                    sign: "!",
+                    postfix: true,
+                    strategy: |child, state| unary_arithmetic("!", V::factorial, child, state),
                 }
             );
         }
         Operation::BinaryPlus => {
-            let prev_top = nodes.pop().ok_or(Error::InvalidSyntax)?;
-            let prev_bot = nodes.pop().ok_or(Error::InvalidSyntax)?;
+            let (prev_bot, prev_top) = pop_pair(nodes, missing_operand)?;
             nodes.push(Node::Binary {
+                span: prev_bot.span().join(prev_top.span()),
                 left: Box::new(prev_bot),
                 right: Box::new(prev_top),
-                sign: '+',
-                strategy: |left, right, state| {
-                    let l = left.eval(state)?;
-                    let r = right.eval(state)?;
-                    Ok(l + r)
-                },
+                sign: "+",
+                strategy: |left, right, state| binary_arithmetic("+", V::add, left, right, state),
             });
         }
         Operation::BinaryMinus => {
-            let prev_top = nodes.pop().ok_or(Error::InvalidSyntax)?;
-            let prev_bot = nodes.pop().ok_or(Error::InvalidSyntax)?;
+            let (prev_bot, prev_top) = pop_pair(nodes, missing_operand)?;
             nodes.push(Node::Binary {
+                span: prev_bot.span().join(prev_top.span()),
                 left: Box::new(prev_bot),
                 right: Box::new(prev_top),
-                sign: '-',
-                strategy: |left, right, state| {
-                    let l = left.eval(state)?;
-                    let r = right.eval(state)?;
-                    Ok(l - r)
-                },
+                sign: "-",
+                strategy: |left, right, state| binary_arithmetic("-", V::subtract, left, right, state),
             });
         }
         Operation::Multiply => {
-            let prev_top = nodes.pop().ok_or(Error::InvalidSyntax)?;
-            let prev_bot = nodes.pop().ok_or(Error::InvalidSyntax)?;
+            let (prev_bot, prev_top) = pop_pair(nodes, missing_operand)?;
             nodes.push(Node::Binary {
+                span: prev_bot.span().join(prev_top.span()),
                 left: Box::new(prev_bot),
                 right: Box::new(prev_top),
-                sign: '*',
-                strategy: |left, right, state| {
-                    let l = left.eval(state)?;
-                    let r = right.eval(state)?;
-                    Ok(l * r)
-                },
+                sign: "*",
+                strategy: |left, right, state| binary_arithmetic("*", V::multiply, left, right, state),
             });
         }
         Operation::Divide => {
-            let prev_top = nodes.pop().ok_or(Error::InvalidSyntax)?;
-            let prev_bot = nodes.pop().ok_or(Error::InvalidSyntax)?;
+            let (prev_bot, prev_top) = pop_pair(nodes, missing_operand)?;
             nodes.push(Node::Binary {
+                span: prev_bot.span().join(prev_top.span()),
                 left: Box::new(prev_bot),
                 right: Box::new(prev_top),
-                sign: '/',
-                strategy: |left, right, state| {
-                    let l = left.eval(state)?;
-                    let r = right.eval(state)?;
-                    Ok(l / r)
+                sign: "/",
+                strategy: |left, right, state| binary_arithmetic("/", V::divide, left, right, state),
+            });
+        },
+        Operation::FloorDivide => {
+            let (prev_bot, prev_top) = pop_pair(nodes, missing_operand)?;
+            nodes.push(Node::Binary {
+                span: prev_bot.span().join(prev_top.span()),
+                left: Box::new(prev_bot),
+                right: Box::new(prev_top),
+                sign: "//",
+                strategy: |left, right, state| binary_arithmetic("//", V::floor_divide, left, right, state),
+            });
+        },
+        Operation::Remainder => {
+            let (prev_bot, prev_top) = pop_pair(nodes, missing_operand)?;
+            nodes.push(Node::Binary {
+                span: prev_bot.span().join(prev_top.span()),
+                left: Box::new(prev_bot),
+                right: Box::new(prev_top),
+                sign: "%",
+                strategy: |left, right, state| match state.remainder {
+                    RemainderMode::Truncated => binary_arithmetic("%", V::remainder, left, right, state),
+                    RemainderMode::Euclidean => binary_arithmetic("%", V::remainder_euclid, left, right, state),
                 },
             });
         },
-        Operation::OpenParenthesis => {
-            let prev = nodes.pop().ok_or(Error::InvalidSyntax)?;
-            nodes.push(
-                Node::Parenthesis {
-                    child: Box::new(prev),
-                }
-            );
+        Operation::Power => {
+            let (prev_bot, prev_top) = pop_pair(nodes, missing_operand)?;
+            nodes.push(Node::Binary {
+                span: prev_bot.span().join(prev_top.span()),
+                left: Box::new(prev_bot),
+                right: Box::new(prev_top),
+                sign: "^",
+                strategy: |left, right, state| binary_arithmetic("^", V::power, left, right, state),
+            });
         },
-        Operation::CloseParenthesis => {
-
+        Operation::Equal => {
+            let (prev_bot, prev_top) = pop_pair(nodes, missing_operand)?;
+            nodes.push(Node::Binary {
+                span: prev_bot.span().join(prev_top.span()),
+                left: Box::new(prev_bot),
+                right: Box::new(prev_top),
+                sign: "==",
+                strategy: |left, right, state| comparison(|l, r| l == r, left, right, state),
+            });
+        },
+        Operation::NotEqual => {
+            let (prev_bot, prev_top) = pop_pair(nodes, missing_operand)?;
+            nodes.push(Node::Binary {
+                span: prev_bot.span().join(prev_top.span()),
+                left: Box::new(prev_bot),
+                right: Box::new(prev_top),
+                sign: "!=",
+                strategy: |left, right, state| comparison(|l, r| l != r, left, right, state),
+            });
+        },
+        Operation::Less => {
+            let (prev_bot, prev_top) = pop_pair(nodes, missing_operand)?;
+            nodes.push(Node::Binary {
+                span: prev_bot.span().join(prev_top.span()),
+                left: Box::new(prev_bot),
+                right: Box::new(prev_top),
+                sign: "<",
+                strategy: |left, right, state| comparison(|l, r| l < r, left, right, state),
+            });
+        },
+        Operation::LessEqual => {
+            let (prev_bot, prev_top) = pop_pair(nodes, missing_operand)?;
+            nodes.push(Node::Binary {
+                span: prev_bot.span().join(prev_top.span()),
+                left: Box::new(prev_bot),
+                right: Box::new(prev_top),
+                sign: "<=",
+                strategy: |left, right, state| comparison(|l, r| l <= r, left, right, state),
+            });
+        },
+        Operation::Greater => {
+            let (prev_bot, prev_top) = pop_pair(nodes, missing_operand)?;
+            nodes.push(Node::Binary {
+                span: prev_bot.span().join(prev_top.span()),
+                left: Box::new(prev_bot),
+                right: Box::new(prev_top),
+                sign: ">",
+                strategy: |left, right, state| comparison(|l, r| l > r, left, right, state),
+            });
+        },
+        Operation::GreaterEqual => {
+            let (prev_bot, prev_top) = pop_pair(nodes, missing_operand)?;
+            nodes.push(Node::Binary {
+                span: prev_bot.span().join(prev_top.span()),
+                left: Box::new(prev_bot),
+                right: Box::new(prev_top),
+                sign: ">=",
+                strategy: |left, right, state| comparison(|l, r| l >= r, left, right, state),
+            });
+        },
+        Operation::And => {
+            let (prev_bot, prev_top) = pop_pair(nodes, missing_operand)?;
+            nodes.push(Node::Binary {
+                span: prev_bot.span().join(prev_top.span()),
+                left: Box::new(prev_bot),
+                right: Box::new(prev_top),
+                sign: "&&",
+                // the right operand is only evaluated when the left one is true
+                strategy: |left, right, state| Ok(V::from_bool(left.eval(state)?.is_truthy() && right.eval(state)?.is_truthy())),
+            });
+        },
+        Operation::Or => {
+            let (prev_bot, prev_top) = pop_pair(nodes, missing_operand)?;
+            nodes.push(Node::Binary {
+                span: prev_bot.span().join(prev_top.span()),
+                left: Box::new(prev_bot),
+                right: Box::new(prev_top),
+                sign: "||",
+                // the right operand is only evaluated when the left one is false
+                strategy: |left, right, state| Ok(V::from_bool(left.eval(state)?.is_truthy() || right.eval(state)?.is_truthy())),
+            });
+        },
+        Operation::BitAnd => {
+            let (prev_bot, prev_top) = pop_pair(nodes, missing_operand)?;
+            nodes.push(Node::Binary {
+                span: prev_bot.span().join(prev_top.span()),
+                left: Box::new(prev_bot),
+                right: Box::new(prev_top),
+                sign: "&",
+                strategy: |left, right, state| binary_arithmetic("&", V::bit_and, left, right, state),
+            });
+        },
+        Operation::BitOr => {
+            let (prev_bot, prev_top) = pop_pair(nodes, missing_operand)?;
+            nodes.push(Node::Binary {
+                span: prev_bot.span().join(prev_top.span()),
+                left: Box::new(prev_bot),
+                right: Box::new(prev_top),
+                sign: "|",
+                strategy: |left, right, state| binary_arithmetic("|", V::bit_or, left, right, state),
+            });
+        },
+        Operation::Xor => {
+            let (prev_bot, prev_top) = pop_pair(nodes, missing_operand)?;
+            nodes.push(Node::Binary {
+                span: prev_bot.span().join(prev_top.span()),
+                left: Box::new(prev_bot),
+                right: Box::new(prev_top),
+                sign: "xor",
+                strategy: |left, right, state| binary_arithmetic("xor", V::bit_xor, left, right, state),
+            });
+        },
+        Operation::ShiftLeft => {
+            let (prev_bot, prev_top) = pop_pair(nodes, missing_operand)?;
+            nodes.push(Node::Binary {
+                span: prev_bot.span().join(prev_top.span()),
+                left: Box::new(prev_bot),
+                right: Box::new(prev_top),
+                sign: "<<",
+                strategy: |left, right, state| binary_arithmetic("<<", V::shift_left, left, right, state),
+            });
+        },
+        Operation::ShiftRight => {
+            let (prev_bot, prev_top) = pop_pair(nodes, missing_operand)?;
+            nodes.push(Node::Binary {
+                span: prev_bot.span().join(prev_top.span()),
+                left: Box::new(prev_bot),
+                right: Box::new(prev_top),
+                sign: ">>",
+                strategy: |left, right, state| binary_arithmetic(">>", V::shift_right, left, right, state),
+            });
+        },
+        Operation::Conditional => {
+            let (Some(otherwise), Some(then), Some(condition)) = (nodes.pop(), nodes.pop(), nodes.pop()) else {
+                return Err(missing_operand);
+            };
+            nodes.push(Node::Conditional {
+                span: condition.span().join(otherwise.span()),
+                condition: Box::new(condition),
+                then: Box::new(then),
+                otherwise: Box::new(otherwise),
+            });
+        },
+        Operation::Question => {
+            return Err(Error::InvalidSyntax(SyntaxError::MissingColon, span));
+        },
+        Operation::OpenParenthesis | Operation::Call => {
+            return Err(Error::InvalidSyntax(SyntaxError::UnbalancedParenthesis, span));
         },
         Operation::Assign => {
-            let prev_top = nodes.pop().ok_or(Error::InvalidSyntax)?;
-            let prev_bot = nodes.pop().ok_or(Error::InvalidSyntax)?;
+            let (prev_bot, prev_top) = pop_pair(nodes, missing_operand)?;
             nodes.push(Node::Binary {
+                span: prev_bot.span().join(prev_top.span()),
                 left: Box::new(prev_bot),
                 right: Box::new(prev_top),
-                sign: '=',
-                strategy: |left, right, state| {
-                    let l = left;
-                    let r = right.eval(state)?; // eval right before left
-                    if let Node::Variable { name } = l.deref() {
-                        state.variables.insert(name.clone(), r);
+                sign: "=",
+                strategy: |left, right, state| match left.unparenthesized() {
+                    Node::Call { name, arguments, .. } => functions::define(name, arguments, right, state),
+                    _ => {
+                        let r = right.eval(state)?; // eval right before left
+                        let name = assignment_target(left, state)?;
+                        state.set(name, r.clone());
                         Ok(r)
-                    } else {
-                        Err(Error::CanOnlyAssignToVariable)
                     }
                 },
             });
         }
+        Operation::PlusAssign => {
+            let (prev_bot, prev_top) = pop_pair(nodes, missing_operand)?;
+            nodes.push(Node::Binary {
+                span: prev_bot.span().join(prev_top.span()),
+                left: Box::new(prev_bot),
+                right: Box::new(prev_top),
+                sign: "+=",
+                strategy: |left, right, state| compound_assignment("+=", V::add, left, right, state),
+            });
+        }
+        Operation::MinusAssign => {
+            let (prev_bot, prev_top) = pop_pair(nodes, missing_operand)?;
+            nodes.push(Node::Binary {
+                span: prev_bot.span().join(prev_top.span()),
+                left: Box::new(prev_bot),
+                right: Box::new(prev_top),
+                sign: "-=",
+                strategy: |left, right, state| compound_assignment("-=", V::subtract, left, right, state),
+            });
+        }
+        Operation::MultiplyAssign => {
+            let (prev_bot, prev_top) = pop_pair(nodes, missing_operand)?;
+            nodes.push(Node::Binary {
+                span: prev_bot.span().join(prev_top.span()),
+                left: Box::new(prev_bot),
+                right: Box::new(prev_top),
+                sign: "*=",
+                strategy: |left, right, state| compound_assignment("*=", V::multiply, left, right, state),
+            });
+        }
+        Operation::DivideAssign => {
+            let (prev_bot, prev_top) = pop_pair(nodes, missing_operand)?;
+            nodes.push(Node::Binary {
+                span: prev_bot.span().join(prev_top.span()),
+                left: Box::new(prev_bot),
+                right: Box::new(prev_top),
+                sign: "/=",
+                strategy: |left, right, state| compound_assignment("/=", V::divide, left, right, state),
+            });
+        }
+        Operation::PreIncrement => {
+            let prev = nodes.pop().ok_or(missing_operand)?;
+            nodes.push(
+                Node::Unary {
+                    span: span.join(prev.span()),
+                    child: Box::new(prev),
+                    sign: "++",
+                    postfix: false,
+                    strategy: |child, state| step("++", V::add, false, child, state),
+                }
+            );
+        }
+        Operation::PreDecrement => {
+            let prev = nodes.pop().ok_or(missing_operand)?;
+            nodes.push(
+                Node::Unary {
+                    span: span.join(prev.span()),
+                    child: Box::new(prev),
+                    sign: "--",
+                    postfix: false,
+                    strategy: |child, state| step("--", V::subtract, false, child, state),
+                }
+            );
+        }
+        Operation::PostIncrement => {
+            let prev = nodes.pop().ok_or(missing_operand)?;
+            nodes.push(
+                Node::Unary {
+                    span: prev.span().join(span),
+                    child: Box::new(prev),
+                    sign: "++",
+                    postfix: true,
+                    strategy: |child, state| step("++", V::add, true, child, state),
+                }
+            );
+        }
+        Operation::PostDecrement => {
+            let prev = nodes.pop().ok_or(missing_operand)?;
+            nodes.push(
+                Node::Unary {
+                    span: prev.span().join(span),
+                    child: Box::new(prev),
+                    sign: "--",
+                    postfix: true,
+                    strategy: |child, state| step("--", V::subtract, true, child, state),
+                }
+            );
+        }
     }
     Ok(())
 }
 
+/// Pop the two topmost nodes, returning them in the order they were pushed
+fn pop_pair<V>(nodes: &mut Vec<Node<V>>, missing_operand: Error) -> Result<(Node<V>, Node<V>), Error> {
+    match (nodes.pop(), nodes.pop()) {
+        (Some(top), Some(bot)) => Ok((bot, top)),
+        _ => Err(missing_operand),
+    }
+}
+
+/// Spans of the two characters of `++` or `--`
+fn split_sign(span: Span) -> (Span, Span) {
+    (Span::new(span.start, span.start + 1), Span::new(span.start + 1, span.end))
+}
+
+/// Whether the token can only be the start of an operand, it cannot follow one
+fn starts_operand(token: Option<&(Token, Span)>) -> bool {
+    matches!(
+        token,
+        Some((Token::Operand(_) | Token::OpenParenthesis | Token::Operation(OperationToken::Tilde | OperationToken::SquareRoot), _))
+    )
+}
+
+/// Replace the arguments of the innermost open call with the call itself
+fn finish_call<V>(calls: &mut Vec<(String, usize)>, nodes: &mut Vec<Node<V>>, span: Span) {
+    let (name, start) = calls.pop().expect("every call marker has a pending call");
+    let arguments = nodes.split_off(start);
+    nodes.push(Node::Call { name, arguments, span });
+}
+
+/// Parse expression into a reusable syntax tree
+pub fn parse<V: Value>(expression: &str) -> Result<Expression<V>, Error> {
+    parse_chars(expression.chars())
+}
+
 /// Evaluate expression from the iterator
-pub fn eval(expression: impl Iterator<Item = char>, state: &mut State) -> Result<i32, Error> {
-    let tokens = TokenIterator { inner: expression.peekable() };
+pub fn eval<V: Value>(expression: impl Iterator<Item = char>, state: &mut State<V>) -> Result<V, Error> {
+    parse_chars(expression)?.eval(state)
+}
 
-    let mut nodes: Vec<Node> = Vec::new();
-    let mut operations: Vec<Operation> = Vec::new();
+/// Evaluate expression from the iterator, changing the state only if the evaluation succeeds
+pub fn eval_atomic<V: Value>(expression: impl Iterator<Item = char>, state: &mut State<V>) -> Result<V, Error> {
+    parse_chars(expression)?.eval_atomic(state)
+}
+
+/// Parse a program of statements separated by `;` or line breaks
+pub fn parse_program<V: Value>(program: &str) -> Result<Program<V>, ProgramError> {
+    parse_program_chars(program.chars())
+}
+
+/// Evaluate the statements of the program in order, resulting in the value of the last one
+pub fn eval_program<V: Value>(program: impl Iterator<Item = char>, state: &mut State<V>) -> Result<Option<V>, ProgramError> {
+    parse_program_chars(program)?.eval(state)
+}
+
+fn parse_chars<V: Value>(expression: impl Iterator<Item = char>) -> Result<Expression<V>, Error> {
+    let mut tokens = TokenIterator::new(expression).peekable();
+    let root = parse_statement(&mut tokens, false)?;
+    let root = root.ok_or(Error::InvalidSyntax(SyntaxError::MissingOperand, Span::new(0, 0)))?;
+    Ok(Expression { root })
+}
+
+fn parse_program_chars<V: Value>(program: impl Iterator<Item = char>) -> Result<Program<V>, ProgramError> {
+    let mut tokens = TokenIterator::new(program).peekable();
+    let mut statements = Vec::new();
+    while tokens.peek().is_some() {
+        match parse_statement(&mut tokens, true) {
+            Ok(Some(root)) => statements.push(Expression { root }),
+            Ok(None) => {}
+            Err(error) => return Err(ProgramError { statement: statements.len() + 1, error }),
+        }
+    }
+    Ok(Program { statements })
+}
+
+/// Parses tokens up to the end of the statement, `None` if the statement is empty.
+///
+/// When `separated`, a `;` ends the statement and so does a line break once the expression is complete
+/// and not inside parenthesis or a conditional. Otherwise line breaks are whitespace and the whole input is one statement.
+fn parse_statement<V: Value, I: Iterator<Item = char>>(tokens: &mut Peekable<TokenIterator<I>>, separated: bool) -> Result<Option<Node<V>>, Error> {
+    let mut nodes: Vec<Node<V>> = Vec::new();
+    let mut operations: Vec<(Operation, Span)> = Vec::new();
+    // name of every function whose argument list is open and the number of nodes before its first argument
+    let mut calls: Vec<(String, usize)> = Vec::new();
 
     let mut expect_operand = true;
-    'outer: for token in tokens {
+    let mut started = false;
+    // span of the `const` keyword when the statement declares a constant
+    let mut declaration = None;
+    let mut end = 0;
+    while let Some((token, mut span)) = tokens.next() {
+        match token {
+            Token::Newline if !separated || expect_operand || is_nested(&operations) => continue,
+            Token::Newline | Token::Semicolon if separated => break,
+            Token::UnterminatedComment => return Err(Error::InvalidSyntax(SyntaxError::UnterminatedComment, span)),
+            Token::Const if !started => {
+                declaration = Some(span);
+                started = true;
+                end = span.end;
+                continue;
+            }
+            _ => {}
+        }
+        started = true;
+        end = span.end;
         let to_be_pushed;
+        // the second sign of a `++` or `--` that does not apply to a variable
+        let mut second_sign = None;
         match (token, expect_operand) {
+            (Token::Operand(OperandsToken::Variable(name)), true) if matches!(tokens.peek(), Some((Token::OpenParenthesis, _))) => {
+                tokens.next();
+                calls.push((name, nodes.len()));
+                operations.push((Operation::Call, span));
+                continue;
+            }
             (Token::Operand(operand), true) => {
                 nodes.push(
                     match operand {
-                        OperandsToken::Constant(value) => Node::Constant { value },
-                        OperandsToken::Variable(name) => Node::Variable { name }
+                        OperandsToken::Constant(literal) => Node::Constant {
+                            value: V::parse_literal(&literal).ok_or(Error::InvalidSyntax(SyntaxError::InvalidNumber, span))?,
+                            literal,
+                            span,
+                        },
+                        OperandsToken::Variable(name) => Node::Variable { name, span }
                     }
                 );
-                to_be_pushed = None;
                 expect_operand = false;
+                continue;
             }
             (Token::Operation(operation), true) => {
                 match operation {
-                    OperationToken::Plus => to_be_pushed = Some(Operation::UnaryPlus),
-                    OperationToken::Minus => to_be_pushed = Some(Operation::UnaryMinus),
-                    _ => return Err(Error::InvalidSyntax),
+                    OperationToken::Plus => to_be_pushed = Operation::UnaryPlus,
+                    OperationToken::Minus => to_be_pushed = Operation::UnaryMinus,
+                    OperationToken::Exclamation => to_be_pushed = Operation::Not,
+                    OperationToken::Tilde => to_be_pushed = Operation::BitNot,
+                    OperationToken::SquareRoot => to_be_pushed = Operation::SquareRoot,
+                    OperationToken::Increment | OperationToken::Decrement if !matches!(tokens.peek(), Some((Token::Operand(OperandsToken::Variable(_)), _))) => {
+                        // `--5` is a double negation
+                        let sign = if operation == OperationToken::Increment { Operation::UnaryPlus } else { Operation::UnaryMinus };
+                        let (first, second) = split_sign(span);
+                        span = first;
+                        to_be_pushed = sign;
+                        second_sign = Some((sign, second));
+                    }
+                    OperationToken::Increment => to_be_pushed = Operation::PreIncrement,
+                    OperationToken::Decrement => to_be_pushed = Operation::PreDecrement,
+                    _ => return Err(Error::InvalidSyntax(SyntaxError::MissingOperand, span)),
                 }
             }
 
             (Token::Operation(operation), false) => {
                 match operation {
-                    OperationToken::Plus => to_be_pushed = Some(Operation::BinaryPlus),
-                    OperationToken::Minus => to_be_pushed = Some(Operation::BinaryMinus),
-                    OperationToken::Multiply => to_be_pushed = Some(Operation::Multiply),
-                    OperationToken::Divide => to_be_pushed = Some(Operation::Divide),
-                    OperationToken::Assign => to_be_pushed = Some(Operation::Assign),
+                    OperationToken::Plus => to_be_pushed = Operation::BinaryPlus,
+                    OperationToken::Minus => to_be_pushed = Operation::BinaryMinus,
+                    OperationToken::Multiply => to_be_pushed = Operation::Multiply,
+                    OperationToken::Divide => to_be_pushed = Operation::Divide,
+                    OperationToken::FloorDivide => to_be_pushed = Operation::FloorDivide,
+                    OperationToken::Remainder => to_be_pushed = Operation::Remainder,
+                    OperationToken::Power => to_be_pushed = Operation::Power,
+                    OperationToken::Assign => to_be_pushed = Operation::Assign,
+                    OperationToken::PlusAssign => to_be_pushed = Operation::PlusAssign,
+                    OperationToken::MinusAssign => to_be_pushed = Operation::MinusAssign,
+                    OperationToken::MultiplyAssign => to_be_pushed = Operation::MultiplyAssign,
+                    OperationToken::DivideAssign => to_be_pushed = Operation::DivideAssign,
+                    OperationToken::Equal => to_be_pushed = Operation::Equal,
+                    OperationToken::NotEqual => to_be_pushed = Operation::NotEqual,
+                    OperationToken::Less => to_be_pushed = Operation::Less,
+                    OperationToken::LessEqual => to_be_pushed = Operation::LessEqual,
+                    OperationToken::Greater => to_be_pushed = Operation::Greater,
+                    OperationToken::GreaterEqual => to_be_pushed = Operation::GreaterEqual,
+                    OperationToken::And => to_be_pushed = Operation::And,
+                    OperationToken::Or => to_be_pushed = Operation::Or,
+                    OperationToken::Question => to_be_pushed = Operation::Question,
+                    OperationToken::BitAnd => to_be_pushed = Operation::BitAnd,
+                    OperationToken::BitOr => to_be_pushed = Operation::BitOr,
+                    OperationToken::Xor => to_be_pushed = Operation::Xor,
+                    OperationToken::ShiftLeft => to_be_pushed = Operation::ShiftLeft,
+                    OperationToken::ShiftRight => to_be_pushed = Operation::ShiftRight,
+                    OperationToken::Tilde | OperationToken::SquareRoot => return Err(Error::InvalidSyntax(SyntaxError::UnexpectedToken, span)),
+                    OperationToken::Increment | OperationToken::Decrement
+                        if !matches!(nodes.last().map(Node::unparenthesized), Some(Node::Variable { .. })) || starts_operand(tokens.peek()) => {
+                        // `5--3` and `a--b` subtract a negative number
+                        let (binary, unary) = if operation == OperationToken::Increment {
+                            (Operation::BinaryPlus, Operation::UnaryPlus)
+                        } else {
+                            (Operation::BinaryMinus, Operation::UnaryMinus)
+                        };
+                        let (first, second) = split_sign(span);
+                        span = first;
+                        to_be_pushed = binary;
+                        second_sign = Some((unary, second));
+                    }
+                    // postfix operations bind tighter than anything, apply them right away
+                    OperationToken::Exclamation => {
+                        collapse(Operation::Factorial, span, &mut nodes)?;
+                        continue;
+                    }
+                    OperationToken::Increment => {
+                        collapse(Operation::PostIncrement, span, &mut nodes)?;
+                        continue;
+                    }
+                    OperationToken::Decrement => {
+                        collapse(Operation::PostDecrement, span, &mut nodes)?;
+                        continue;
+                    }
                 }
                 expect_operand = true;
             }
 
             (Token::OpenParenthesis, true) => {
-                to_be_pushed = Some(Operation::OpenParenthesis);
-                expect_operand = true;
+                operations.push((Operation::OpenParenthesis, span));
+                continue;
             },
             (Token::CloseParenthesis, false) => {
-                to_be_pushed = Some(Operation::CloseParenthesis);
-                expect_operand = false;
+                loop {
+                    match operations.pop() {
+                        Some((Operation::OpenParenthesis, open)) => {
+                            let child = nodes.pop().ok_or(Error::InvalidSyntax(SyntaxError::MissingOperand, span))?;
+                            nodes.push(Node::Parenthesis { child: Box::new(child), span: open.join(span) });
+                            break;
+                        }
+                        Some((Operation::Call, name_span)) => {
+                            finish_call(&mut calls, &mut nodes, name_span.join(span));
+                            break;
+                        }
+                        Some((operation, operation_span)) => collapse(operation, operation_span, &mut nodes)?,
+                        None => return Err(Error::InvalidSyntax(SyntaxError::UnbalancedParenthesis, span)),
+                    }
+                }
+                continue;
+            },
+            (Token::CloseParenthesis, true) => {
+                // a call without arguments, `f()`
+                match (operations.last(), calls.last()) {
+                    (Some(&(Operation::Call, name_span)), Some((_, start))) if *start == nodes.len() => {
+                        operations.pop();
+                        finish_call(&mut calls, &mut nodes, name_span.join(span));
+                        expect_operand = false;
+                        continue;
+                    }
+                    _ => return Err(Error::InvalidSyntax(SyntaxError::MissingOperand, span)),
+                }
+            },
+            (Token::Comma, false) => {
+                loop {
+                    match operations.last() {
+                        Some((Operation::Call, _)) => break,
+                        Some((Operation::OpenParenthesis, _)) | None => {
+                            return Err(Error::InvalidSyntax(SyntaxError::UnexpectedToken, span));
+                        }
+                        Some(&(operation, operation_span)) => {
+                            operations.pop();
+                            collapse(operation, operation_span, &mut nodes)?;
+                        }
+                    }
+                }
+                expect_operand = true;
+                continue;
+            },
+            (Token::Colon, false) => {
+                loop {
+                    match operations.pop() {
+                        Some((Operation::Question, _)) => break,
+                        Some((Operation::OpenParenthesis | Operation::Call, _)) | None => {
+                            return Err(Error::InvalidSyntax(SyntaxError::UnexpectedToken, span));
+                        }
+                        Some((operation, operation_span)) => collapse(operation, operation_span, &mut nodes)?,
+                    }
+                }
+                operations.push((Operation::Conditional, span));
+                expect_operand = true;
+                continue;
+            },
+            (Token::Comma | Token::Colon, true) => {
+                return Err(Error::InvalidSyntax(SyntaxError::MissingOperand, span));
             },
 
-            (_, _) => return Err(Error::InvalidSyntax),
+            (_, _) => return Err(Error::InvalidSyntax(SyntaxError::UnexpectedToken, span)),
         }
 
-        while let (Some(&x), Some(y)) = (operations.last(), to_be_pushed) {
-            if let (Operation::OpenParenthesis, Operation::CloseParenthesis) = (x, y) {
-                operations.pop();
-                continue 'outer;
-            }
-            if x <= y {
+        while let Some(&(x, x_span)) = operations.last() {
+            if x.collapses_before(&to_be_pushed) {
                 operations.pop();
-                collapse(x, &mut nodes)?;
+                collapse(x, x_span, &mut nodes)?;
             } else {
                 break;
             }
         }
-
-        match (operations.last(), to_be_pushed) {
-            (Some(Operation::OpenParenthesis), Some(Operation::CloseParenthesis)) => {
-                operations.pop();
-                collapse(Operation::CloseParenthesis, &mut nodes)?;
-                continue;
-            }
-            (Some(&x), Some(y)) => {
-                while x <= y {
-                    operations.pop();
-                    collapse(x, &mut nodes)?;
-                }
-
-            },
-            _ => {}
-        }
-        if let Some(y) = to_be_pushed {
-            operations.push(y);
+        operations.push((to_be_pushed, span));
+        // a prefix operation never collapses the ones before it
+        if let Some(second_sign) = second_sign {
+            operations.push(second_sign);
         }
     }
 
-    while let Some(operation) = operations.pop() {
-        collapse(operation, &mut nodes)?
+    if !started {
+        return Ok(None);
+    }
+    if expect_operand {
+        return Err(Error::InvalidSyntax(SyntaxError::MissingOperand, Span::new(end, end)));
+    }
+    while let Some((operation, span)) = operations.pop() {
+        collapse(operation, span, &mut nodes)?
     }
 
+    let root = nodes.pop().ok_or(Error::InvalidSyntax(SyntaxError::MissingOperand, Span::new(end, end)))?;
+    match declaration {
+        None => Ok(Some(root)),
+        Some(keyword) => declare(keyword, root).map(Some),
+    }
+}
 
-    nodes.pop().ok_or(Error::InvalidSyntax)?.eval(state)
+/// Turns the assignment following the `const` keyword into the declaration of a constant
+fn declare<V: Value>(keyword: Span, root: Node<V>) -> Result<Node<V>, Error> {
+    let span = keyword.join(root.span());
+    match root {
+        Node::Binary { left, right, sign: "=", .. } => match *left {
+            Node::Variable { name, .. } => Ok(Node::Declaration { name, value: right, span }),
+            _ => Err(Error::InvalidSyntax(SyntaxError::InvalidDeclaration, span)),
+        },
+        _ => Err(Error::InvalidSyntax(SyntaxError::InvalidDeclaration, span)),
+    }
+}
 
+/// Whether a parenthesis, an argument list or a conditional is still open
+fn is_nested(operations: &[(Operation, Span)]) -> bool {
+    operations.iter().any(|(operation, _)| matches!(operation, Operation::OpenParenthesis | Operation::Call | Operation::Question))
 }
@@ -0,0 +1,45 @@
+use std::fmt::{Debug, Display, Formatter};
+use std::str::FromStr;
+use crate::error::Error;
+use crate::evaluator::parse;
+use crate::node::Node;
+use crate::state::State;
+use crate::value::Value;
+
+/// A parsed expression that can be evaluated any number of times
+#[derive(Clone)]
+pub struct Expression<V = i32> {
+    pub(crate) root: Node<V>,
+}
+
+impl<V: Value> Expression<V> {
+    /// Evaluates the expression against the given state
+    pub fn eval(&self, state: &mut State<V>) -> Result<V, Error> {
+        self.root.eval(state)
+    }
+
+    /// Evaluates the expression so that it changes the state only if it succeeds
+    pub fn eval_atomic(&self, state: &mut State<V>) -> Result<V, Error> {
+        state.transaction(|state| self.eval(state))
+    }
+}
+
+impl<V: Value> FromStr for Expression<V> {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        parse(s)
+    }
+}
+
+impl<V: Value> Display for Expression<V> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.root)
+    }
+}
+
+impl<V: Value> Debug for Expression<V> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{:?}", self.root)
+    }
+}
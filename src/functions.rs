@@ -0,0 +1,242 @@
+use std::fmt::{Debug, Display, Formatter};
+use std::sync::Arc;
+use crate::error::{Error, HostError};
+use crate::node::Node;
+use crate::span::Span;
+use crate::state::{OverflowPolicy, State};
+use crate::value::{ArithmeticError, Value};
+
+/// Number of arguments a function accepts
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum Arity {
+    Exact(usize),
+    AtLeast(usize),
+}
+
+impl Arity {
+    pub fn accepts(&self, count: usize) -> bool {
+        match self {
+            Arity::Exact(expected) => count == *expected,
+            Arity::AtLeast(minimum) => count >= *minimum,
+        }
+    }
+}
+
+impl Display for Arity {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Arity::Exact(expected) => write!(f, "{}", expected),
+            Arity::AtLeast(minimum) => write!(f, "at least {}", minimum),
+        }
+    }
+}
+
+/// Implementation of a built-in function, the arguments are already checked against its arity
+pub(crate) type Builtin<V> = fn(&[V], OverflowPolicy) -> Result<V, ArithmeticError>;
+
+/// Closure of a function registered by the host application
+type Native<V> = dyn Fn(&[V]) -> Result<V, HostError> + Send + Sync;
+
+/// A function implemented by the host application, see `State::register_function`
+pub struct NativeFunction<V> {
+    pub arity: Arity,
+    function: Arc<Native<V>>,
+}
+
+impl<V> NativeFunction<V> {
+    pub fn new(arity: Arity, function: impl Fn(&[V]) -> Result<V, HostError> + Send + Sync + 'static) -> Self {
+        NativeFunction { arity, function: Arc::new(function) }
+    }
+
+    /// Calls the function, the number of arguments is not checked against its arity
+    pub fn call(&self, args: &[V]) -> Result<V, HostError> {
+        (self.function)(args)
+    }
+}
+
+impl<V> Clone for NativeFunction<V> {
+    fn clone(&self) -> Self {
+        NativeFunction { arity: self.arity, function: Arc::clone(&self.function) }
+    }
+}
+
+impl<V> Debug for NativeFunction<V> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("NativeFunction").field("arity", &self.arity).finish_non_exhaustive()
+    }
+}
+
+/// A function defined inside an expression, `f(x, y) = x^2 + y`
+pub struct UserFunction<V> {
+    pub parameters: Vec<String>,
+    pub(crate) body: Arc<Node<V>>,
+}
+
+impl<V> Clone for UserFunction<V> {
+    fn clone(&self) -> Self {
+        UserFunction { parameters: self.parameters.clone(), body: Arc::clone(&self.body) }
+    }
+}
+
+impl<V> Debug for UserFunction<V> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("UserFunction").field("parameters", &self.parameters).finish_non_exhaustive()
+    }
+}
+
+/// Stores the function in the state, every parameter has to be a distinct variable name.
+/// The body is not evaluated, so defining a function always results in zero.
+pub(crate) fn define<V: Value>(name: &str, parameters: &[Node<V>], body: &Node<V>, state: &mut State<V>) -> Result<V, Error> {
+    let mut names: Vec<String> = Vec::with_capacity(parameters.len());
+    for parameter in parameters {
+        match parameter {
+            Node::Variable { name, span } if names.contains(name) => {
+                return Err(Error::DuplicateParameter(name.clone(), *span));
+            }
+            Node::Variable { name, .. } => names.push(name.clone()),
+            _ => return Err(Error::InvalidParameter(parameter.span())),
+        }
+    }
+    state.definitions.insert(name.to_string(), UserFunction { parameters: names, body: Arc::new(body.clone()) });
+    Ok(V::from_i32(0))
+}
+
+/// Evaluates the arguments and calls the function with them.
+/// Functions defined in expressions take precedence over the ones registered by the host,
+/// which in turn take precedence over built-in ones of the same name.
+pub(crate) fn call<V: Value>(name: &str, arguments: &[Node<V>], span: Span, state: &mut State<V>) -> Result<V, Error> {
+    if let Some(function) = state.definitions.get(name).cloned() {
+        return call_user_function(name, function, arguments, span, state);
+    }
+    if let Some(native) = state.functions.get(name).cloned() {
+        let values = evaluate_arguments(name, native.arity, arguments, span, state)?;
+        return native.call(&values).map_err(|error| Error::Host { function: name.to_string(), error, span });
+    }
+
+    let (arity, function) = builtin::<V>(name).ok_or_else(|| Error::UnknownFunction(name.to_string(), span))?;
+    let values = evaluate_arguments(name, arity, arguments, span, state)?;
+    function(&values, state.overflow).map_err(|e| {
+        let operands = values.iter().map(V::to_string).collect::<Vec<_>>().join(", ");
+        match e {
+            ArithmeticError::Overflow => Error::Overflow { op: name.to_string(), lhs: None, rhs: operands, span },
+            ArithmeticError::DivisionByZero => Error::DivisionByZero(span),
+            ArithmeticError::InvalidOperand => Error::InvalidOperand { op: name.to_string(), operand: operands, span },
+        }
+    })
+}
+
+/// Evaluates the body in a new scope in which the parameters are bound to the arguments,
+/// so they shadow variables of the same name only until the call returns.
+/// The scopes of the caller are set aside meanwhile, the body only sees its parameters and the global variables.
+/// Errors in the body point at the call, the body was parsed from another source.
+fn call_user_function<V: Value>(name: &str, function: UserFunction<V>, arguments: &[Node<V>], span: Span, state: &mut State<V>) -> Result<V, Error> {
+    let values = evaluate_arguments(name, Arity::Exact(function.parameters.len()), arguments, span, state)?;
+    if state.call_depth >= state.max_call_depth {
+        return Err(Error::RecursionLimit(name.to_string(), span));
+    }
+
+    let caller = std::mem::replace(&mut state.scopes, vec![function.parameters.into_iter().zip(values).collect()]);
+    state.call_depth += 1;
+    let result = function.body.eval(state).map_err(|error| error.with_span(span));
+    state.call_depth -= 1;
+    state.scopes = caller;
+    result
+}
+
+/// Checks the number of arguments and evaluates them from left to right
+fn evaluate_arguments<V: Value>(name: &str, arity: Arity, arguments: &[Node<V>], span: Span, state: &mut State<V>) -> Result<Vec<V>, Error> {
+    if !arity.accepts(arguments.len()) {
+        return Err(Error::ArityMismatch { name: name.to_string(), expected: arity, found: arguments.len(), span });
+    }
+    arguments.iter().map(|argument| argument.eval(state)).collect()
+}
+
+/// Looks up a built-in function by name
+pub(crate) fn builtin<V: Value>(name: &str) -> Option<(Arity, Builtin<V>)> {
+    let function: (Arity, Builtin<V>) = match name {
+        "abs" => (Arity::Exact(1), |args, overflow| args[0].abs(overflow)),
+        "min" => (Arity::AtLeast(1), |args, _| Ok(select(args, |candidate, best| candidate < best))),
+        "max" => (Arity::AtLeast(1), |args, _| Ok(select(args, |candidate, best| candidate > best))),
+        "sqrt" => (Arity::Exact(1), |args, _| args[0].sqrt()),
+        "pow" => (Arity::Exact(2), |args, overflow| args[0].power(&args[1], overflow)),
+        "floor" => (Arity::Exact(1), |args, _| Ok(args[0].floor())),
+        "ceil" => (Arity::Exact(1), |args, _| Ok(args[0].ceil())),
+        "round" => (Arity::Exact(1), |args, _| Ok(args[0].round())),
+        "gcd" => (Arity::AtLeast(1), |args, overflow| fold(args, overflow, gcd)),
+        "lcm" => (Arity::AtLeast(1), |args, overflow| fold(args, overflow, lcm)),
+        "log" => (Arity::Exact(1), |args, _| logarithm(&args[0], f64::ln)),
+        "log2" => (Arity::Exact(1), |args, _| logarithm(&args[0], f64::log2)),
+        "log10" => (Arity::Exact(1), |args, _| logarithm(&args[0], f64::log10)),
+        "exp" => (Arity::Exact(1), |args, _| real(&args[0], f64::exp)),
+        "sin" => (Arity::Exact(1), |args, _| real(&args[0], f64::sin)),
+        "cos" => (Arity::Exact(1), |args, _| real(&args[0], f64::cos)),
+        "tan" => (Arity::Exact(1), |args, _| real(&args[0], f64::tan)),
+        "asin" => (Arity::Exact(1), |args, _| real(&args[0], f64::asin)),
+        "acos" => (Arity::Exact(1), |args, _| real(&args[0], f64::acos)),
+        "atan" => (Arity::Exact(1), |args, _| real(&args[0], f64::atan)),
+        "atan2" => (Arity::Exact(2), |args, _| V::from_f64(args[0].to_f64().atan2(args[1].to_f64())).ok_or(ArithmeticError::Overflow)),
+        _ => return None,
+    };
+    Some(function)
+}
+
+/// The argument that is preferred over all others
+fn select<V: Value>(args: &[V], prefer: fn(&V, &V) -> bool) -> V {
+    let mut best = &args[0];
+    for candidate in &args[1..] {
+        if prefer(candidate, best) {
+            best = candidate;
+        }
+    }
+    best.clone()
+}
+
+/// Combines all arguments pairwise from left to right
+fn fold<V: Value>(args: &[V], overflow: OverflowPolicy, combine: fn(&V, &V, OverflowPolicy) -> Result<V, ArithmeticError>) -> Result<V, ArithmeticError> {
+    let mut result = args[0].abs(overflow)?;
+    for arg in &args[1..] {
+        result = combine(&result, arg, overflow)?;
+    }
+    Ok(result)
+}
+
+/// Greatest common divisor of two integers, always non-negative
+fn gcd<V: Value>(a: &V, b: &V, overflow: OverflowPolicy) -> Result<V, ArithmeticError> {
+    if !a.is_integer() || !b.is_integer() {
+        return Err(ArithmeticError::InvalidOperand);
+    }
+    let zero = V::from_i32(0);
+    let (mut a, mut b) = (a.clone(), b.clone());
+    while b != zero {
+        let rest = a.remainder(&b, overflow)?;
+        a = b;
+        b = rest;
+    }
+    a.abs(overflow)
+}
+
+/// Least common multiple of two integers, always non-negative
+fn lcm<V: Value>(a: &V, b: &V, overflow: OverflowPolicy) -> Result<V, ArithmeticError> {
+    let divisor = gcd(a, b, overflow)?;
+    if divisor == V::from_i32(0) {
+        return Ok(divisor);
+    }
+    a.divide(&divisor, overflow)?.multiply(b, overflow)?.abs(overflow)
+}
+
+/// Computes a real function through `f64` and converts the result back
+fn real<V: Value>(x: &V, function: fn(f64) -> f64) -> Result<V, ArithmeticError> {
+    let result = function(x.to_f64());
+    if result.is_nan() {
+        return Err(ArithmeticError::InvalidOperand);
+    }
+    V::from_f64(result).ok_or(ArithmeticError::Overflow)
+}
+
+/// Logarithms are only defined for positive numbers
+fn logarithm<V: Value>(x: &V, function: fn(f64) -> f64) -> Result<V, ArithmeticError> {
+    if *x <= V::from_i32(0) {
+        return Err(ArithmeticError::InvalidOperand);
+    }
+    real(x, function)
+}
@@ -1,6 +1,15 @@
 pub mod error;
+pub mod span;
 pub mod state;
+pub mod cells;
 pub mod evaluator;
+pub mod expression;
+pub mod functions;
 mod node;
+pub mod persistence;
+pub mod program;
+pub mod resolver;
+#[allow(clippy::module_inception)]
 mod tests;
 mod token;
+pub mod value;
@@ -1,11 +1,22 @@
 use std::io::Write;
-use math_evaluator::evaluator::eval;
+use math_evaluator::evaluator::eval_program;
 use math_evaluator::state::State;
+use math_evaluator::value::{BigInt, BigRational, FractionStyle, Value};
 
 fn main() {
-    let mut state = State {
-        variables: std::collections::HashMap::new(),
-    };
+    match std::env::args().nth(1).as_deref() {
+        None | Some("--integer") => repl::<i32>(),
+        Some("--float") => repl::<f64>(),
+        Some("--big") => repl::<BigInt>(),
+        Some("--rational") => repl::<BigRational>(),
+        Some(mode) => eprintln!("Unknown mode: {}, expected --integer, --float, --big or --rational", mode),
+    }
+}
+
+fn repl<V: Value>() {
+    let mut state = State::<V>::new();
+    let mut style = FractionStyle::Fraction;
+    let mut radix = 10;
     loop {
         print!("\n> ");
         if let Err(e) = std::io::stdout().flush() {
@@ -22,8 +33,34 @@ fn main() {
             println!("Goodbye!");
             break;
         }
+        if let Some(command) = input.strip_prefix(':') {
+            match command.split_whitespace().collect::<Vec<_>>().as_slice() {
+                ["format", "fraction"] => style = FractionStyle::Fraction,
+                ["format", "mixed"] => style = FractionStyle::Mixed,
+                ["format", "decimal"] => style = FractionStyle::Decimal(10),
+                ["format", "decimal", digits] => match digits.parse() {
+                    Ok(digits) => style = FractionStyle::Decimal(digits),
+                    Err(e) => println!("Invalid number of digits: {}", e),
+                },
+                ["radix", base @ ("2" | "8" | "10" | "16")] => radix = base.parse().unwrap(),
+                ["save", path] => if let Err(e) = std::fs::write(path, state.save()) {
+                    println!("Unable to save {}: {}", path, e);
+                },
+                ["load", path] => match std::fs::read_to_string(path) {
+                    Ok(text) => if let Err(e) = state.load(&text) {
+                        println!("Unable to load {}: {}", path, e);
+                    },
+                    Err(e) => println!("Unable to read {}: {}", path, e),
+                },
+                _ => println!("Unknown command, expected :format fraction|mixed|decimal [digits], :radix 2|8|10|16, :save file or :load file"),
+            }
+            continue;
+        }
 
-        let res = eval(input.chars(), &mut state);
-        println!("{:?}", res);
+        match eval_program(input.chars(), &mut state) {
+            Ok(Some(value)) => println!("{}", value.display_radix(radix).unwrap_or_else(|| value.display_as(style))),
+            Ok(None) => {}
+            Err(e) => println!("{}", e.render(input)),
+        }
     }
-}
\ No newline at end of file
+}
@@ -1,70 +1,169 @@
 use std::fmt::{Debug, Display, Formatter};
 use crate::error::Error;
+use crate::functions;
+use crate::span::Span;
 use crate::state::State;
+use crate::value::Value;
+
+pub(crate) type UnaryStrategy<V> = fn(child: &Box<Node<V>>, &mut State<V>) -> Result<V, Error>;
+pub(crate) type BinaryStrategy<V> = fn(left: &Box<Node<V>>, right: &Box<Node<V>>, &mut State<V>) -> Result<V, Error>;
 
 /// Operation nodes for parser tree
 #[derive(Clone)]
-pub(crate) enum Node {
+pub(crate) enum Node<V> {
     Variable {
         name: String,
+        span: Span,
     },
     Constant {
-        value: i32,
+        value: V,
+        /// The literal as written, printed instead of the value so that the printed tree parses back to the same value
+        literal: String,
+        span: Span,
     },
     Parenthesis {
-        child: Box<Node>,
+        child: Box<Node<V>>,
+        span: Span,
     },
     Unary {
-        child: Box<Node>,
-        sign: char,
-        strategy: fn(child: &Box<Node>, &mut State) -> Result<i32, Error>,
+        child: Box<Node<V>>,
+        sign: &'static str,
+        /// Whether the sign is written after the operand, like the factorial
+        postfix: bool,
+        strategy: UnaryStrategy<V>,
+        span: Span,
     },
     Binary {
-        left: Box<Node>,
-        right: Box<Node>,
-        sign: char,
-        strategy: fn(left: &Box<Node>, right: &Box<Node>, &mut State) -> Result<i32, Error>,
+        left: Box<Node<V>>,
+        right: Box<Node<V>>,
+        sign: &'static str,
+        strategy: BinaryStrategy<V>,
+        span: Span,
+    },
+    Call {
+        name: String,
+        arguments: Vec<Node<V>>,
+        span: Span,
+    },
+    /// `const name = value`
+    Declaration {
+        name: String,
+        value: Box<Node<V>>,
+        span: Span,
+    },
+    /// `condition ? then : otherwise`, only the taken branch is evaluated
+    Conditional {
+        condition: Box<Node<V>>,
+        then: Box<Node<V>>,
+        otherwise: Box<Node<V>>,
+        span: Span,
     },
 }
 
-impl Node {
-    /// Evaluates the value of the node
-    pub(crate) fn eval(&self, state: &mut State) -> Result<i32, Error> {
+impl<V: Value> Node<V> {
+    /// Evaluates the value of the node, at most `State::max_nesting` nodes may be evaluated inside each other
+    pub(crate) fn eval(&self, state: &mut State<V>) -> Result<V, Error> {
+        if state.nesting >= state.max_nesting {
+            return Err(Error::NestingLimit(self.span()));
+        }
+        state.nesting += 1;
+        let result = self.evaluate(state);
+        state.nesting -= 1;
+        result
+    }
+
+    fn evaluate(&self, state: &mut State<V>) -> Result<V, Error> {
         match self {
-            Node::Variable { name } => {
-                match state.variables.get(name) {
-                    Some(value) => Ok(*value),
-                    None => Err(Error::UninitializedVariable(name.clone())),
+            Node::Variable { name, span } => {
+                match state.get(name) {
+                    Some(value) => Ok(value),
+                    None => Err(Error::UninitializedVariable(name.clone(), *span)),
                 }
             },
-            Node::Constant { value } => Ok(*value),
+            Node::Constant { value, .. } => Ok(value.clone()),
             Node::Unary { child, strategy, .. } => strategy(child, state),
             Node::Binary { left, right, strategy, .. } => strategy(left, right, state),
             Node::Parenthesis {child, ..} => child.eval(state),
+            Node::Call { name, arguments, span } => functions::call(name, arguments, *span, state),
+            Node::Declaration { name, value, span } => {
+                if state.constants.contains_key(name) {
+                    return Err(Error::AssignToConstant(name.clone(), *span));
+                }
+                let value = value.eval(state)?;
+                state.define_constant(name.clone(), value.clone());
+                Ok(value)
+            }
+            Node::Conditional { condition, then, otherwise, .. } => {
+                if condition.eval(state)?.is_truthy() {
+                    then.eval(state)
+                } else {
+                    otherwise.eval(state)
+                }
+            }
+        }
+    }
+
+    /// The node itself with any enclosing parenthesis removed
+    pub(crate) fn unparenthesized(&self) -> &Node<V> {
+        match self {
+            Node::Parenthesis { child, .. } => child.unparenthesized(),
+            node => node,
+        }
+    }
+
+    /// Part of the source the node was parsed from
+    pub(crate) fn span(&self) -> Span {
+        match self {
+            Node::Variable { span, .. }
+            | Node::Constant { span, .. }
+            | Node::Parenthesis { span, .. }
+            | Node::Unary { span, .. }
+            | Node::Binary { span, .. }
+            | Node::Call { span, .. }
+            | Node::Declaration { span, .. }
+            | Node::Conditional { span, .. } => *span,
         }
     }
 }
 
-impl Display for Node {
+impl<V: Value> Display for Node<V> {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
-            Node::Variable { name } => write!(f, "{}", name),
-            Node::Constant { value } => write!(f, "{}", value),
+            Node::Variable { name, .. } => write!(f, "{}", name),
+            Node::Constant { literal, .. } => write!(f, "{}", literal),
+            Node::Unary { child, sign, postfix: true, .. } => write!(f, "({}{})", child, sign),
             Node::Unary { child, sign, .. } => write!(f, "({}{})", sign, child),
             Node::Binary { left, right, sign, .. } => write!(f, "({} {} {})", left, sign, right),
-            Node::Parenthesis {child, ..} => write!(f, "({})", child),
+            // operations are parenthesized already, so printing and parsing again gives the same text
+            Node::Parenthesis { child, .. } if matches!(**child, Node::Variable { .. } | Node::Constant { .. } | Node::Call { .. }) => write!(f, "({})", child),
+            Node::Parenthesis { child, .. } => write!(f, "{}", child),
+            Node::Call { name, arguments, .. } => {
+                write!(f, "{}(", name)?;
+                for (i, argument) in arguments.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ", ")?;
+                    }
+                    write!(f, "{}", argument)?;
+                }
+                write!(f, ")")
+            }
+            Node::Declaration { name, value, .. } => write!(f, "(const {} = {})", name, value),
+            Node::Conditional { condition, then, otherwise, .. } => write!(f, "({} ? {} : {})", condition, then, otherwise),
         }
     }
 }
 
-impl Debug for Node {
+impl<V: Value> Debug for Node<V> {
     fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
         match self {
-            Node::Variable { name } => write!(f, "(Var: {})", name),
-            Node::Constant { value } => write!(f, "(Const: {})", value),
+            Node::Variable { name, .. } => write!(f, "(Var: {})", name),
+            Node::Constant { value, .. } => write!(f, "(Const: {})", value),
             Node::Unary { child, sign, .. } => write!(f, "(Unary {}: {})", sign, child),
             Node::Binary { left, right, sign, .. } => write!(f, "(Binary {}: {}, {})", sign, left, right),
             Node::Parenthesis {child, ..} => write!(f, "(Nested: {})", child),
+            Node::Call { name, arguments, .. } => write!(f, "(Call {}: {:?})", name, arguments),
+            Node::Declaration { name, value, .. } => write!(f, "(Const {}: {})", name, value),
+            Node::Conditional { condition, then, otherwise, .. } => write!(f, "(Conditional: {}, {}, {})", condition, then, otherwise),
         }
     }
 }
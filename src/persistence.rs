@@ -0,0 +1,138 @@
+//! Saving a `State` as JSON and loading it back.
+//!
+//! The format is versioned so that files written by older versions can still be read:
+//! ```json
+//! {
+//!   "version": 1,
+//!   "settings": { "overflow": "error", "remainder": "truncated", "assignment": "innermost", "max_call_depth": 256 },
+//!   "variables": { "x": "5" },
+//!   "constants": { "rate": "2" },
+//!   "functions": { "f": { "parameters": ["x"], "body": "(x * rate)" } }
+//! }
+//! ```
+//! Values are stored as text so that big integers and fractions keep their precision.
+
+use std::collections::{BTreeMap, HashMap};
+use std::sync::Arc;
+use serde::{Deserialize, Serialize};
+use crate::error::{Error, LoadError};
+use crate::evaluator::parse;
+use crate::functions::UserFunction;
+use crate::node::Node;
+use crate::span::Span;
+use crate::state::{AssignmentScope, OverflowPolicy, RemainderMode, State};
+use crate::value::Value;
+
+/// Version of the format written by `State::save`
+pub const FORMAT_VERSION: u32 = 1;
+
+#[derive(Serialize, Deserialize)]
+struct SavedState {
+    version: u32,
+    settings: Settings,
+    #[serde(default)]
+    variables: BTreeMap<String, String>,
+    #[serde(default)]
+    constants: BTreeMap<String, String>,
+    #[serde(default)]
+    functions: BTreeMap<String, SavedFunction>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct Settings {
+    overflow: OverflowPolicy,
+    remainder: RemainderMode,
+    assignment: AssignmentScope,
+    max_call_depth: usize,
+}
+
+#[derive(Serialize, Deserialize)]
+struct SavedFunction {
+    parameters: Vec<String>,
+    body: String,
+}
+
+impl<V: Value> State<V> {
+    /// The global variables, constants, functions defined in expressions and settings as JSON.
+    /// Nested scopes and functions registered by the host are not saved.
+    pub fn save(&self) -> String {
+        let variables = self.variables.names().into_iter()
+            .filter_map(|name| Some((name.clone(), self.variables.get(&name)?.to_string())))
+            .collect();
+        let saved = SavedState {
+            version: FORMAT_VERSION,
+            settings: Settings {
+                overflow: self.overflow,
+                remainder: self.remainder,
+                assignment: self.assignment,
+                max_call_depth: self.max_call_depth,
+            },
+            variables,
+            constants: self.constants.iter().map(|(name, value)| (name.clone(), value.to_string())).collect(),
+            functions: self.definitions.iter()
+                .map(|(name, function)| (name.clone(), SavedFunction { parameters: function.parameters.clone(), body: function.body.to_string() }))
+                .collect(),
+        };
+        serde_json::to_string_pretty(&saved).expect("a saved state is always valid JSON")
+    }
+
+    /// Replaces the variables, constants, functions defined in expressions and settings
+    /// with the ones saved by `State::save`. Functions registered by the host are kept.
+    /// Nothing is changed if the text cannot be loaded. `max_nesting` is not saved as it depends
+    /// on the stack of the thread, so the loaded `max_call_depth` is capped at the current `max_nesting`.
+    pub fn load(&mut self, text: &str) -> Result<(), LoadError> {
+        let saved: SavedState = serde_json::from_str(text).map_err(|e| LoadError::Format(e.to_string()))?;
+        if saved.version > FORMAT_VERSION {
+            return Err(LoadError::UnsupportedVersion(saved.version));
+        }
+
+        let variables = parse_values::<V>(saved.variables)?;
+        let constants = parse_values::<V>(saved.constants)?;
+        let mut definitions = HashMap::new();
+        for (name, function) in saved.functions {
+            check_parameters::<V>(&function.parameters).map_err(|error| LoadError::InvalidFunction { name: name.clone(), error })?;
+            let body = parse::<V>(&function.body).map_err(|error| LoadError::InvalidFunction { name: name.clone(), error })?;
+            definitions.insert(name, UserFunction { parameters: function.parameters, body: Arc::new(body.root) });
+        }
+
+        for name in self.variables.names() {
+            self.variables.remove(&name);
+        }
+        for (name, value) in variables {
+            self.variables.set(&name, value);
+        }
+        self.constants = constants.into_iter().collect();
+        self.definitions = definitions;
+        self.scopes.clear();
+        self.overflow = saved.settings.overflow;
+        self.remainder = saved.settings.remainder;
+        self.assignment = saved.settings.assignment;
+        self.max_call_depth = saved.settings.max_call_depth.min(self.max_nesting);
+        Ok(())
+    }
+}
+
+fn parse_values<V: Value>(values: BTreeMap<String, String>) -> Result<Vec<(String, V)>, LoadError> {
+    values.into_iter()
+        .map(|(name, value)| match V::parse_saved(&value) {
+            Some(parsed) => Ok((name, parsed)),
+            None => Err(LoadError::InvalidValue { name, value }),
+        })
+        .collect()
+}
+
+/// Checks the parameters the same way as a definition in an expression does,
+/// the spans of errors refer to the name of the parameter.
+fn check_parameters<V: Value>(parameters: &[String]) -> Result<(), Error> {
+    for (index, parameter) in parameters.iter().enumerate() {
+        let span = Span::new(0, parameter.chars().count());
+        match parse::<V>(parameter).map(|expression| expression.root) {
+            Ok(Node::Variable { name, .. }) if name == *parameter => {}
+            _ => return Err(Error::InvalidParameter(span)),
+        }
+        if parameters[..index].contains(parameter) {
+            return Err(Error::DuplicateParameter(parameter.clone(), span));
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,64 @@
+use std::fmt::{Debug, Display, Formatter};
+use std::str::FromStr;
+use crate::error::ProgramError;
+use crate::evaluator::parse_program;
+use crate::expression::Expression;
+use crate::state::State;
+use crate::value::Value;
+
+/// A parsed sequence of statements that are evaluated in order against the same state
+#[derive(Clone)]
+pub struct Program<V = i32> {
+    pub(crate) statements: Vec<Expression<V>>,
+}
+
+impl<V: Value> Program<V> {
+    /// Evaluates every statement, resulting in the value of the last one or `None` for an empty program.
+    /// Evaluation stops at the first failing statement, changes made by the previous ones are kept.
+    pub fn eval(&self, state: &mut State<V>) -> Result<Option<V>, ProgramError> {
+        Ok(self.eval_all(state)?.pop())
+    }
+
+    /// Evaluates every statement so that the program changes the state only if all of them succeed
+    pub fn eval_atomic(&self, state: &mut State<V>) -> Result<Option<V>, ProgramError> {
+        state.transaction(|state| self.eval(state))
+    }
+
+    /// Evaluates every statement, resulting in the value of each one
+    pub fn eval_all(&self, state: &mut State<V>) -> Result<Vec<V>, ProgramError> {
+        self.statements.iter()
+            .enumerate()
+            .map(|(index, statement)| statement.eval(state).map_err(|error| ProgramError { statement: index + 1, error }))
+            .collect()
+    }
+
+    pub fn statements(&self) -> &[Expression<V>] {
+        &self.statements
+    }
+}
+
+impl<V: Value> FromStr for Program<V> {
+    type Err = ProgramError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        parse_program(s)
+    }
+}
+
+impl<V: Value> Display for Program<V> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        for (i, statement) in self.statements.iter().enumerate() {
+            if i > 0 {
+                write!(f, "; ")?;
+            }
+            write!(f, "{}", statement)?;
+        }
+        Ok(())
+    }
+}
+
+impl<V: Value> Debug for Program<V> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_list().entries(&self.statements).finish()
+    }
+}
@@ -0,0 +1,67 @@
+use std::collections::HashMap;
+use std::fmt::Debug;
+
+/// Storage of the variables an expression reads and assigns.
+///
+/// `State` keeps its variables behind this trait so they can live in a database,
+/// the environment or a layered configuration. A `HashMap` is used by default.
+pub trait VariableResolver<V>: CloneResolver<V> + Debug + Send + Sync {
+    /// Current value of the variable, `None` if it is not set
+    fn get(&self, name: &str) -> Option<V>;
+
+    fn set(&mut self, name: &str, value: V);
+
+    fn contains(&self, name: &str) -> bool {
+        self.get(name).is_some()
+    }
+
+    /// Unsets the variable and returns its previous value.
+    /// Resolvers that cannot unset variables may keep them and return `None`.
+    fn remove(&mut self, name: &str) -> Option<V>;
+
+    /// Names of all variables, used by `State::save`.
+    /// Resolvers that cannot list their variables return none, so they are not saved.
+    fn names(&self) -> Vec<String> {
+        Vec::new()
+    }
+}
+
+/// Allows a boxed resolver to be cloned together with the `State` owning it,
+/// implemented for every resolver that is `Clone`
+pub trait CloneResolver<V> {
+    fn clone_box(&self) -> Box<dyn VariableResolver<V>>;
+}
+
+impl<V, T: VariableResolver<V> + Clone + 'static> CloneResolver<V> for T {
+    fn clone_box(&self) -> Box<dyn VariableResolver<V>> {
+        Box::new(self.clone())
+    }
+}
+
+impl<V> Clone for Box<dyn VariableResolver<V>> {
+    fn clone(&self) -> Self {
+        self.clone_box()
+    }
+}
+
+impl<V: Clone + Debug + Send + Sync + 'static> VariableResolver<V> for HashMap<String, V> {
+    fn get(&self, name: &str) -> Option<V> {
+        HashMap::get(self, name).cloned()
+    }
+
+    fn set(&mut self, name: &str, value: V) {
+        self.insert(name.to_string(), value);
+    }
+
+    fn contains(&self, name: &str) -> bool {
+        self.contains_key(name)
+    }
+
+    fn remove(&mut self, name: &str) -> Option<V> {
+        HashMap::remove(self, name)
+    }
+
+    fn names(&self) -> Vec<String> {
+        self.keys().cloned().collect()
+    }
+}
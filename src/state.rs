@@ -1,7 +1,277 @@
 use std::collections::HashMap;
+use serde::{Deserialize, Serialize};
+use crate::error::HostError;
+use crate::functions::{Arity, NativeFunction, UserFunction};
+use crate::resolver::VariableResolver;
+use crate::value::Value;
 
 /// The state of the variables
 #[derive(Clone, Debug)]
-pub struct State {
-    pub variables: HashMap<String, i32>,
-}
\ No newline at end of file
+pub struct State<V = i32> {
+    /// The outermost, global scope
+    pub variables: Box<dyn VariableResolver<V>>,
+    /// Nested scopes above the global one, the innermost is last
+    pub(crate) scopes: Vec<HashMap<String, V>>,
+    /// Changes of global variables made inside a transaction, `None` marks a removed variable
+    pub(crate) staging: Option<HashMap<String, Option<V>>>,
+    /// Which scope an assignment writes to while nested scopes are open
+    pub assignment: AssignmentScope,
+    /// Names that can be read like variables but not assigned to, they take precedence over variables
+    pub constants: HashMap<String, V>,
+    /// What happens when an arithmetic operation overflows
+    pub overflow: OverflowPolicy,
+    /// Sign convention of the `%` operation
+    pub remainder: RemainderMode,
+    /// Functions provided by the host application, callable from expressions
+    pub functions: HashMap<String, NativeFunction<V>>,
+    /// Functions defined inside expressions, `f(x) = x^2`
+    pub definitions: HashMap<String, UserFunction<V>>,
+    /// How many calls of functions defined inside expressions may be nested
+    pub max_call_depth: usize,
+    pub(crate) call_depth: usize,
+    /// How deeply the evaluation of operations may nest, counting every operation, parenthesis and call
+    /// together with the ones in the bodies of called functions. Every level takes some of the thread's stack,
+    /// the default of 256 fits the 2 MiB stack of spawned threads. A larger limit needs a larger stack.
+    pub max_nesting: usize,
+    pub(crate) nesting: usize,
+}
+
+/// Behaviour of arithmetic operations whose result does not fit into the value type
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum OverflowPolicy {
+    /// Report an `Error::Overflow`
+    #[default]
+    Error,
+    /// Wrap around at the boundary of the type
+    Wrapping,
+    /// Clamp to the minimum or maximum value of the type
+    Saturating,
+}
+
+/// Which scope an assignment writes to while nested scopes are open.
+/// Inside the body of a function defined in an expression an assignment always writes to the parameter
+/// of that name, or to the global variable if there is none.
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AssignmentScope {
+    /// Always the innermost scope, shadowing variables of outer scopes,
+    /// so that assignments never leak out of a scope
+    #[default]
+    Innermost,
+    /// The innermost scope the variable is already bound in,
+    /// or the innermost scope if it is not bound anywhere
+    NearestBinding,
+}
+
+/// Which remainder `%` computes when the operands have different signs.
+/// Floor division `//` always rounds toward negative infinity regardless of the mode.
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RemainderMode {
+    /// The remainder has the sign of the dividend, `-7 % 3 == -1`
+    #[default]
+    Truncated,
+    /// The remainder is never negative, `-7 % 3 == 2`
+    Euclidean,
+}
+
+impl<V: Value> State<V> {
+    /// A state without variables, with the constants `pi`, `e` and `tau` (also `π` and `τ`)
+    /// if the value type can represent them
+    pub fn new() -> Self {
+        Self::with_variables(HashMap::new())
+    }
+
+    /// A state whose variables are stored by the given resolver
+    pub fn with_variables(variables: impl VariableResolver<V> + 'static) -> Self {
+        let mut constants = HashMap::new();
+        let builtin: [(&[&str], f64); 3] = [
+            (&["pi", "π"], std::f64::consts::PI),
+            (&["tau", "τ"], std::f64::consts::TAU),
+            (&["e"], std::f64::consts::E),
+        ];
+        for (names, value) in builtin {
+            match V::from_f64(value) {
+                Some(constant) if constant.to_f64() == value => {
+                    for name in names {
+                        constants.insert(name.to_string(), constant.clone());
+                    }
+                }
+                _ => {}
+            }
+        }
+        State {
+            variables: Box::new(variables),
+            scopes: Vec::new(),
+            staging: None,
+            assignment: AssignmentScope::default(),
+            constants,
+            overflow: OverflowPolicy::default(),
+            remainder: RemainderMode::default(),
+            functions: HashMap::new(),
+            definitions: HashMap::new(),
+            max_call_depth: 256,
+            call_depth: 0,
+            max_nesting: 256,
+            nesting: 0,
+        }
+    }
+
+    /// Makes the closure callable from expressions under the given name,
+    /// replacing a previously registered function or shadowing a built-in one.
+    /// Calls with a number of arguments not accepted by `arity` are rejected before the closure runs.
+    pub fn register_function(&mut self, name: impl Into<String>, arity: Arity, function: impl Fn(&[V]) -> Result<V, HostError> + Send + Sync + 'static) {
+        self.functions.insert(name.into(), NativeFunction::new(arity, function));
+    }
+
+    /// Makes the name read-only with the given value, a variable of the same name is removed
+    pub fn define_constant(&mut self, name: impl Into<String>, value: V) {
+        let name = name.into();
+        self.remove_global(&name);
+        self.constants.insert(name, value);
+    }
+
+    /// Value of the variable or constant, looked up from the innermost scope to the global one.
+    /// Constants take precedence over global variables, but not over the bindings of nested scopes
+    /// such as the parameters of a function.
+    pub fn get(&self, name: &str) -> Option<V> {
+        match self.scopes.iter().rev().find_map(|scope| scope.get(name)) {
+            Some(value) => Some(value.clone()),
+            None => self.constants.get(name).cloned().or_else(|| self.get_global(name)),
+        }
+    }
+
+    /// Whether the name refers to a constant, that is it is not bound in a nested scope
+    pub(crate) fn is_constant(&self, name: &str) -> bool {
+        self.constants.contains_key(name) && !self.scopes.iter().any(|scope| scope.contains_key(name))
+    }
+
+    /// Assigns the variable in the scope selected by `State::assignment`
+    pub fn set(&mut self, name: &str, value: V) {
+        let bound = self.scopes.iter().rposition(|scope| scope.contains_key(name));
+        let index = match self.assignment {
+            // only the parameters are local to a function body, other assignments update the global variables
+            _ if self.call_depth > 0 => bound,
+            AssignmentScope::Innermost => self.scopes.len().checked_sub(1),
+            AssignmentScope::NearestBinding => match bound {
+                Some(index) => Some(index),
+                None if self.get_global(name).is_some() => None,
+                None => self.scopes.len().checked_sub(1),
+            },
+        };
+        let scope = index.map(|index| &mut self.scopes[index]);
+        match scope {
+            Some(scope) => {
+                scope.insert(name.to_string(), value);
+            }
+            None => self.set_global(name, value),
+        }
+    }
+
+    /// Global variable, including the changes staged by a running transaction
+    fn get_global(&self, name: &str) -> Option<V> {
+        match self.staging.as_ref().and_then(|staging| staging.get(name)) {
+            Some(staged) => staged.clone(),
+            None => self.variables.get(name),
+        }
+    }
+
+    fn set_global(&mut self, name: &str, value: V) {
+        match &mut self.staging {
+            Some(staging) => {
+                staging.insert(name.to_string(), Some(value));
+            }
+            None => self.variables.set(name, value),
+        }
+    }
+
+    fn remove_global(&mut self, name: &str) {
+        match &mut self.staging {
+            Some(staging) => {
+                staging.insert(name.to_string(), None);
+            }
+            None => {
+                self.variables.remove(name);
+            }
+        }
+    }
+
+    /// Runs `f` so that either all of its changes to the state are kept or none are.
+    ///
+    /// Assignments to global variables are staged and only passed to the variable resolver
+    /// once `f` succeeds, so a failing evaluation never writes to it.
+    /// Scopes, constants and function definitions are rolled back if `f` fails.
+    /// Nested transactions are only committed together with the outermost one.
+    pub fn transaction<T, E>(&mut self, f: impl FnOnce(&mut Self) -> Result<T, E>) -> Result<T, E> {
+        let scopes = self.scopes.clone();
+        let constants = self.constants.clone();
+        let definitions = self.definitions.clone();
+        let staging = self.staging.clone();
+        if self.staging.is_none() {
+            self.staging = Some(HashMap::new());
+        }
+
+        let result = f(self);
+        match &result {
+            Ok(_) if staging.is_none() => {
+                for (name, value) in self.staging.take().unwrap_or_default() {
+                    match value {
+                        Some(value) => self.variables.set(&name, value),
+                        None => {
+                            self.variables.remove(&name);
+                        }
+                    }
+                }
+            }
+            Ok(_) => {}
+            Err(_) => {
+                self.scopes = scopes;
+                self.constants = constants;
+                self.definitions = definitions;
+                self.staging = staging;
+            }
+        }
+        result
+    }
+
+    /// Copy of the whole state, including the variables cloned from the resolver
+    pub fn snapshot(&self) -> Snapshot<V> {
+        Snapshot(self.clone())
+    }
+
+    /// Returns to the state of the snapshot, discarding every change made since it was taken
+    pub fn restore(&mut self, snapshot: Snapshot<V>) {
+        *self = snapshot.0;
+    }
+
+    /// Opens a new innermost scope
+    pub fn push_scope(&mut self) {
+        self.scopes.push(HashMap::new());
+    }
+
+    /// Opens a new innermost scope with the given variables
+    pub fn push_scope_with(&mut self, variables: HashMap<String, V>) {
+        self.scopes.push(variables);
+    }
+
+    /// Closes the innermost scope and returns its variables, `None` if only the global scope is left
+    pub fn pop_scope(&mut self) -> Option<HashMap<String, V>> {
+        self.scopes.pop()
+    }
+
+    /// Number of nested scopes above the global one
+    pub fn scope_depth(&self) -> usize {
+        self.scopes.len()
+    }
+}
+
+/// A copy of a `State` taken by `State::snapshot`
+#[derive(Clone, Debug)]
+pub struct Snapshot<V = i32>(State<V>);
+
+impl<V: Value> Default for State<V> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
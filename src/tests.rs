@@ -1,13 +1,22 @@
 #[cfg(test)]
 mod tests {
-    use crate::error::Error::{CanOnlyAssignToVariable, InvalidSyntax, UninitializedVariable};
-    use crate::evaluator::eval;
-    use crate::state::State;
+    use crate::cells::Sheet;
+    use crate::error::Error::{ArityMismatch, AssignToConstant, CanOnlyAssignToVariable, CyclicFormula, DivisionByZero, DuplicateParameter, Host, InvalidOperand, InvalidParameter, InvalidSyntax, NestingLimit, Overflow, RecursionLimit, UninitializedVariable, UnknownFunction};
+    use crate::error::{HostError, LoadError, ProgramError};
+    use crate::error::SyntaxError::{InvalidDeclaration, AssignmentInFormula, InvalidFormula, InvalidNumber, MissingColon, MissingOperand, UnbalancedParenthesis, UnexpectedToken, UnterminatedComment};
+    use crate::evaluator::{eval, eval_atomic, eval_program, parse, parse_program};
+    use crate::expression::Expression;
+    use crate::functions::Arity;
+    use crate::resolver::VariableResolver;
+    use crate::span::Span;
+    use crate::state::{AssignmentScope, OverflowPolicy, RemainderMode, State};
+    use crate::value::{BigInt, BigRational, FractionStyle, Value};
 
     #[test]
     fn constant() {
         let mut state = State {
-            variables: std::collections::HashMap::from([("x".to_string(), 42)]),
+            variables: Box::new(std::collections::HashMap::from([("x".to_string(), 42)])),
+            ..State::new()
         };
 
         let result = eval("42".chars(), &mut state);
@@ -17,7 +26,8 @@ mod tests {
     #[test]
     fn variable() {
         let mut state = State {
-            variables: std::collections::HashMap::from([("x".to_string(), 42)]),
+            variables: Box::new(std::collections::HashMap::from([("x".to_string(), 42)])),
+            ..State::new()
         };
 
         let result = eval("x".chars(), &mut state);
@@ -26,9 +36,7 @@ mod tests {
 
     #[test]
     fn unary_operator() {
-        let mut state = State {
-            variables: std::collections::HashMap::new(),
-        };
+        let mut state = State::new();
 
         let result = eval("-12".chars(), &mut state);
         assert_eq!(result, Ok(-12));
@@ -38,9 +46,7 @@ mod tests {
 
     #[test]
     fn binary_operator() {
-        let mut state = State {
-            variables: std::collections::HashMap::new(),
-        };
+        let mut state = State::new();
 
         let result = eval("12 + 12".chars(), &mut state);
         assert_eq!(result, Ok(24));
@@ -55,9 +61,7 @@ mod tests {
 
     #[test]
     fn order_of_operations() {
-        let mut state = State {
-            variables: std::collections::HashMap::new(),
-        };
+        let mut state = State::new();
 
         let result = eval("10 + 12 * 4".chars(), &mut state);
         assert_eq!(result, Ok(58));
@@ -66,9 +70,7 @@ mod tests {
     }
     #[test]
     fn chained_operations() {
-        let mut state = State {
-            variables: std::collections::HashMap::new(),
-        };
+        let mut state = State::new();
 
         let result = eval("24 / 8 / 2".chars(), &mut state);
         assert_eq!(result, Ok(1));
@@ -86,9 +88,7 @@ mod tests {
 
     #[test]
     fn parenthesis() {
-        let mut state = State {
-            variables: std::collections::HashMap::new(),
-        };
+        let mut state = State::new();
 
         let result = eval("(12 + 12) * 5 + 2 * 4 ".chars(), &mut state);
         assert_eq!(result, Ok(128));
@@ -96,9 +96,7 @@ mod tests {
 
     #[test]
     fn variables() {
-        let mut state = State {
-            variables: std::collections::HashMap::new(),
-        };
+        let mut state = State::new();
 
         let result = eval("x = 12".chars(), &mut state);
         assert_eq!(result, Ok(12));
@@ -108,9 +106,7 @@ mod tests {
 
     #[test]
     fn variables_in_parenthesis() {
-        let mut state = State {
-            variables: std::collections::HashMap::new(),
-        };
+        let mut state = State::new();
 
         let result = eval("(x = 12) + (x = 7)".chars(), &mut state);
         assert_eq!(result, Ok(19));
@@ -120,17 +116,813 @@ mod tests {
 
     #[test]
     fn invalid_syntax() {
-        let mut state = State {
-            variables: std::collections::HashMap::new(),
-        };
+        let mut state: State = State::new();
 
         let result = eval("x + 1".chars(), &mut state);
-        assert_eq!(result, Err(UninitializedVariable("x".to_string())));
+        assert_eq!(result, Err(UninitializedVariable("x".to_string(), Span::new(0, 1))));
         let result = eval("2 *** 1".chars(), &mut state);
-        assert_eq!(result, Err(InvalidSyntax));
+        assert_eq!(result, Err(InvalidSyntax(MissingOperand, Span::new(4, 5))));
         let result = eval("1 = 5".chars(), &mut state);
-        assert_eq!(result, Err(CanOnlyAssignToVariable));
+        assert_eq!(result, Err(CanOnlyAssignToVariable(Span::new(0, 1))));
     }
-}
 
+    #[test]
+    fn parse_once_eval_many() {
+        let expression = parse("x * 2 + 1").unwrap();
+
+        let mut state = State {
+            variables: Box::new(std::collections::HashMap::from([("x".to_string(), 1)])),
+            ..State::new()
+        };
+        assert_eq!(expression.eval(&mut state), Ok(3));
+        state.variables.set("x", 20);
+        assert_eq!(expression.eval(&mut state), Ok(41));
+
+        let mut other = State {
+            variables: Box::new(std::collections::HashMap::from([("x".to_string(), -4)])),
+            ..State::new()
+        };
+        assert_eq!(expression.clone().eval(&mut other), Ok(-7));
+    }
+
+    #[test]
+    fn parsed_expression_is_shareable() {
+        fn assert_send_sync<T: Send + Sync>() {}
+        assert_send_sync::<Expression>();
+
+        let expression: Expression = "a = 5".parse().unwrap();
+        let mut state = State::new();
+        assert_eq!(expression.eval(&mut state), Ok(5));
+        assert_eq!(state.variables.get("a"), Some(5));
+        assert_eq!(parse::<i32>("2 *").unwrap_err(), InvalidSyntax(MissingOperand, Span::new(3, 3)));
+    }
+
+    #[test]
+    fn error_spans() {
+        assert_eq!(parse::<i32>("(1 + 2").unwrap_err(), InvalidSyntax(UnbalancedParenthesis, Span::new(0, 1)));
+        assert_eq!(parse::<i32>("1 + 2)").unwrap_err(), InvalidSyntax(UnbalancedParenthesis, Span::new(5, 6)));
+        assert_eq!(parse::<i32>("2 * ()").unwrap_err(), InvalidSyntax(MissingOperand, Span::new(5, 6)));
+        assert_eq!(parse::<i32>("1 + 2 3").unwrap_err(), InvalidSyntax(UnexpectedToken, Span::new(6, 7)));
+        assert_eq!(parse::<i32>("1 $ 2").unwrap_err(), InvalidSyntax(UnexpectedToken, Span::new(2, 3)));
+        assert_eq!(parse::<i32>("").unwrap_err(), InvalidSyntax(MissingOperand, Span::new(0, 0)));
+
+        let mut state: State = State::new();
+        let result = eval("1 + (2 * 3) = 4".chars(), &mut state);
+        assert_eq!(result, Err(CanOnlyAssignToVariable(Span::new(4, 11))));
+        let result = eval("1 + (yy * 3)".chars(), &mut state);
+        assert_eq!(result, Err(UninitializedVariable("yy".to_string(), Span::new(5, 7))));
+    }
+
+    #[test]
+    fn render_error() {
+        let source = "x = 1 +\n  (2 * foo";
+        let error = parse::<i32>(source).unwrap_err();
+        assert_eq!(error.render(source), "Invalid syntax: unbalanced parenthesis\n2 |   (2 * foo\n  |   ^");
+
+        let source = "1 + length * 2";
+        let mut state: State = State::new();
+        let error = eval(source.chars(), &mut state).unwrap_err();
+        assert_eq!(error.render(source), "Uninitialized variable: length\n1 | 1 + length * 2\n  |     ^^^^^^");
+    }
+
+    #[test]
+    fn division_by_zero() {
+        let mut state: State = State::new();
+
+        let result = eval("1 / 0".chars(), &mut state);
+        assert_eq!(result, Err(DivisionByZero(Span::new(4, 5))));
+        let result = eval("x = 7 / (3 - 3)".chars(), &mut state);
+        assert_eq!(result, Err(DivisionByZero(Span::new(8, 15))));
+        assert_eq!(state.variables.get("x"), None);
+    }
+
+    #[test]
+    fn overflow_policy() {
+        let mut state = State {
+            variables: Box::new(std::collections::HashMap::from([("min".to_string(), i32::MIN), ("max".to_string(), i32::MAX)])),
+            ..State::new()
+        };
+
+        let result = eval("max + 1".chars(), &mut state);
+        assert_eq!(result, Err(Overflow { op: "+".to_string(), lhs: Some(i32::MAX.to_string()), rhs: 1.to_string(), span: Span::new(0, 7) }));
+        let result = eval("-min".chars(), &mut state);
+        assert_eq!(result, Err(Overflow { op: "-".to_string(), lhs: None, rhs: i32::MIN.to_string(), span: Span::new(1, 4) }));
+        let result = eval("min / -1".chars(), &mut state);
+        assert_eq!(result, Err(Overflow { op: "/".to_string(), lhs: Some(i32::MIN.to_string()), rhs: (-1).to_string(), span: Span::new(0, 8) }));
+
+        state.overflow = OverflowPolicy::Wrapping;
+        assert_eq!(eval("max + 1".chars(), &mut state), Ok(i32::MIN));
+        assert_eq!(eval("-min".chars(), &mut state), Ok(i32::MIN));
+        assert_eq!(eval("max * 2".chars(), &mut state), Ok(-2));
+
+        state.overflow = OverflowPolicy::Saturating;
+        assert_eq!(eval("max + 1".chars(), &mut state), Ok(i32::MAX));
+        assert_eq!(eval("-min".chars(), &mut state), Ok(i32::MAX));
+        assert_eq!(eval("min - max".chars(), &mut state), Ok(i32::MIN));
+        assert_eq!(eval("1 / 0".chars(), &mut state), Err(DivisionByZero(Span::new(4, 5))));
+    }
+
+    #[test]
+    fn float_mode() {
+        let mut state = State::<f64>::new();
+
+        assert_eq!(eval("2.75".chars(), &mut state), Ok(2.75));
+        assert_eq!(eval(".5 + 1.".chars(), &mut state), Ok(1.5));
+        assert_eq!(eval("1e-9 * 2E+3".chars(), &mut state), Ok(1e-9 * 2e3));
+        assert_eq!(eval("12 / 8".chars(), &mut state), Ok(1.5));
+        assert_eq!(eval("r = 2.5".chars(), &mut state), Ok(2.5));
+        assert_eq!(eval("r * r".chars(), &mut state), Ok(6.25));
+        assert_eq!(eval("1 / 0.0".chars(), &mut state), Err(DivisionByZero(Span::new(4, 7))));
+        assert_eq!(eval("1.2.3".chars(), &mut state), Err(InvalidSyntax(InvalidNumber, Span::new(0, 5))));
+    }
+
+    #[test]
+    fn integer_mode_rejects_fractions() {
+        let mut state: State = State::new();
+
+        assert_eq!(eval("3.14".chars(), &mut state), Err(InvalidSyntax(InvalidNumber, Span::new(0, 4))));
+        assert_eq!(eval("1 + 12abc".chars(), &mut state), Err(InvalidSyntax(InvalidNumber, Span::new(4, 9))));
+        assert_eq!(eval("99999999999".chars(), &mut state), Err(InvalidSyntax(InvalidNumber, Span::new(0, 11))));
+        assert_eq!(eval("1 + .".chars(), &mut state), Err(InvalidSyntax(UnexpectedToken, Span::new(4, 5))));
+    }
+
+    #[test]
+    fn big_integer_mode() {
+        let mut state = State::<BigInt>::new();
+
+        let literal = "123456789012345678901234567890";
+        assert_eq!(eval(literal.chars(), &mut state), Ok(literal.parse().unwrap()));
+        assert_eq!(eval("30!".chars(), &mut state), Ok("265252859812191058636308480000000".parse().unwrap()));
+        assert_eq!(eval("x = 4294967296 * 4294967296".chars(), &mut state), Ok("18446744073709551616".parse().unwrap()));
+        assert_eq!(eval("-x / 3".chars(), &mut state), Ok("-6148914691236517205".parse().unwrap()));
+        assert_eq!(eval("x / (2 - 2)".chars(), &mut state), Err(DivisionByZero(Span::new(4, 11))));
+    }
+
+    #[test]
+    fn factorial() {
+        let mut state: State = State::new();
+
+        assert_eq!(eval("5!".chars(), &mut state), Ok(120));
+        assert_eq!(eval("-3! + (1 + 2)!".chars(), &mut state), Ok(0));
+        assert_eq!(eval("2 * 3!".chars(), &mut state), Ok(12));
+        assert_eq!(eval("13!".chars(), &mut state), Err(Overflow { op: "!".to_string(), lhs: None, rhs: "13".to_string(), span: Span::new(0, 2) }));
+        assert_eq!(eval("(0 - 1)!".chars(), &mut state), Err(InvalidOperand { op: "!".to_string(), operand: "-1".to_string(), span: Span::new(0, 7) }));
+        assert_eq!(eval("2 * !".chars(), &mut state), Err(InvalidSyntax(MissingOperand, Span::new(5, 5))));
+
+        state.overflow = OverflowPolicy::Saturating;
+        assert_eq!(eval("13!".chars(), &mut state), Ok(i32::MAX));
+    }
+
+    #[test]
+    fn rational_mode() {
+        let mut state = State::<BigRational>::new();
+        let fraction = |numerator: i32, denominator: i32| BigRational::new(numerator.into(), denominator.into());
+
+        assert_eq!(eval("1/3 + 1/6".chars(), &mut state), Ok(fraction(1, 2)));
+        assert_eq!(eval("12 / 8".chars(), &mut state), Ok(fraction(3, 2)));
+        assert_eq!(eval("0.75 - 1e-2".chars(), &mut state), Ok(fraction(37, 50)));
+        assert_eq!(eval("2.5e1 / 10".chars(), &mut state), Ok(fraction(5, 2)));
+        assert_eq!(eval("(1/2)!".chars(), &mut state), Err(InvalidOperand { op: "!".to_string(), operand: "1/2".to_string(), span: Span::new(0, 5) }));
+        assert_eq!(eval("1 / (1/2 - 0.5)".chars(), &mut state), Err(DivisionByZero(Span::new(4, 15))));
+    }
+
+    #[test]
+    fn rational_formatting() {
+        let value = BigRational::new((-22).into(), 7.into());
+
+        assert_eq!(value.display_as(FractionStyle::Fraction), "-22/7");
+        assert_eq!(value.display_as(FractionStyle::Mixed), "-3 1/7");
+        assert_eq!(value.display_as(FractionStyle::Decimal(4)), "-3.1429");
+        assert_eq!(BigRational::new(1.into(), 2.into()).display_as(FractionStyle::Mixed), "1/2");
+        assert_eq!(BigRational::new(6.into(), 3.into()).display_as(FractionStyle::Mixed), "2");
+        assert_eq!(BigRational::new(1.into(), 8.into()).display_as(FractionStyle::Decimal(10)), "0.125");
+        assert_eq!(BigRational::new((-1).into(), 1000.into()).display_as(FractionStyle::Decimal(2)), "0");
+        assert_eq!(7.display_as(FractionStyle::Decimal(3)), "7");
+    }
+
+    #[test]
+    fn power() {
+        let mut state: State = State::new();
+
+        assert_eq!(eval("2 ^ 10".chars(), &mut state), Ok(1024));
+        assert_eq!(eval("2 ** 10".chars(), &mut state), Ok(1024));
+        assert_eq!(eval("-2^2".chars(), &mut state), Ok(-4));
+        assert_eq!(eval("(-2)^2".chars(), &mut state), Ok(4));
+        assert_eq!(eval("2^3^2".chars(), &mut state), Ok(512));
+        assert_eq!(eval("3 * 2^3 + 1".chars(), &mut state), Ok(25));
+        assert_eq!(eval("2^-1".chars(), &mut state), Ok(0));
+        assert_eq!(eval("-2 * 3".chars(), &mut state), Ok(-6));
+        assert_eq!(eval("x = 2^3!".chars(), &mut state), Ok(64));
+        assert_eq!(eval("0^-1".chars(), &mut state), Err(DivisionByZero(Span::new(2, 4))));
+        assert_eq!(eval("2^31".chars(), &mut state), Err(Overflow { op: "^".to_string(), lhs: Some("2".to_string()), rhs: "31".to_string(), span: Span::new(0, 4) }));
+
+        let mut state = State::<BigInt>::new();
+        assert_eq!(eval("2^200".chars(), &mut state), Ok(BigInt::from(2).pow(200)));
+        assert_eq!(eval("(-1)^-3".chars(), &mut state), Ok(BigInt::from(-1)));
+
+        let mut state = State::<BigRational>::new();
+        assert_eq!(eval("(2/3)^-2".chars(), &mut state), Ok(BigRational::new(9.into(), 4.into())));
+        assert_eq!(eval("4^(1/2)".chars(), &mut state), Err(InvalidOperand { op: "^".to_string(), operand: "1/2".to_string(), span: Span::new(2, 7) }));
 
+        let mut state = State::<f64>::new();
+        assert_eq!(eval("4^0.5".chars(), &mut state), Ok(2.0));
+    }
+
+    #[test]
+    fn remainder_and_floor_division() {
+        let mut state: State = State::new();
+
+        assert_eq!(eval("17 % 5".chars(), &mut state), Ok(2));
+        assert_eq!(eval("-7 % 3".chars(), &mut state), Ok(-1));
+        assert_eq!(eval("7 % -3".chars(), &mut state), Ok(1));
+        assert_eq!(eval("-7 // 2".chars(), &mut state), Ok(-4));
+        assert_eq!(eval("7 // 2 * 2 + 7 % 2".chars(), &mut state), Ok(7));
+        assert_eq!(eval("5 % 0".chars(), &mut state), Err(DivisionByZero(Span::new(4, 5))));
+        assert_eq!(eval("5 // (1 - 1)".chars(), &mut state), Err(DivisionByZero(Span::new(5, 12))));
+
+        state.remainder = RemainderMode::Euclidean;
+        assert_eq!(eval("-7 % 3".chars(), &mut state), Ok(2));
+        assert_eq!(eval("-7 % -3".chars(), &mut state), Ok(2));
+
+        let mut state = State::<BigRational>::new();
+        assert_eq!(eval("(7/2) // 1".chars(), &mut state), Ok(BigRational::from_integer(3.into())));
+        assert_eq!(eval("-(7/2) % 1".chars(), &mut state), Ok(BigRational::new((-1).into(), 2.into())));
+        state.remainder = RemainderMode::Euclidean;
+        assert_eq!(eval("-(7/2) % 1".chars(), &mut state), Ok(BigRational::new(1.into(), 2.into())));
+
+        let mut state = State::<f64>::new();
+        assert_eq!(eval("-7.5 // 2".chars(), &mut state), Ok(-4.0));
+        assert_eq!(eval("7.5 % 2".chars(), &mut state), Ok(1.5));
+    }
+
+    #[test]
+    fn function_calls() {
+        let mut state: State = State::new();
+
+        assert_eq!(eval("abs(-3) + max(1, 7, 4) * min(2, -2)".chars(), &mut state), Ok(-11));
+        assert_eq!(eval("pow(2, 1 + 2)!".chars(), &mut state), Ok(40320));
+        assert_eq!(eval("gcd(12, 18) + lcm(4, 6) + sqrt(17)".chars(), &mut state), Ok(22));
+        assert_eq!(eval("max(abs(-5), (2 + 1) * 2)".chars(), &mut state), Ok(6));
+        assert_eq!(eval("min = 3".chars(), &mut state), Ok(3));
+        assert_eq!(eval("min(min, 2)".chars(), &mut state), Ok(2));
+        assert_eq!(parse::<i32>("max(1, 2)").unwrap().to_string(), "max(1, 2)");
+
+        assert_eq!(eval("1 + foo(2)".chars(), &mut state), Err(UnknownFunction("foo".to_string(), Span::new(4, 10))));
+        assert_eq!(eval("sqrt(1, 2)".chars(), &mut state), Err(ArityMismatch { name: "sqrt".to_string(), expected: Arity::Exact(1), found: 2, span: Span::new(0, 10) }));
+        assert_eq!(eval("max()".chars(), &mut state), Err(ArityMismatch { name: "max".to_string(), expected: Arity::AtLeast(1), found: 0, span: Span::new(0, 5) }));
+        assert_eq!(eval("sqrt(-4)".chars(), &mut state), Err(InvalidOperand { op: "sqrt".to_string(), operand: "-4".to_string(), span: Span::new(0, 8) }));
+        assert_eq!(parse::<i32>("max(1,)").unwrap_err(), InvalidSyntax(MissingOperand, Span::new(6, 7)));
+        assert_eq!(parse::<i32>("max(1, 2").unwrap_err(), InvalidSyntax(UnbalancedParenthesis, Span::new(0, 3)));
+        assert_eq!(parse::<i32>("(1, 2)").unwrap_err(), InvalidSyntax(UnexpectedToken, Span::new(2, 3)));
+
+        let mut state = State::<f64>::new();
+        assert_eq!(eval("floor(2.5) + ceil(2.5) + round(2.5)".chars(), &mut state), Ok(8.0));
+        assert!((eval("sin(1)^2 + cos(1)^2".chars(), &mut state).unwrap() - 1.0).abs() < 1e-12);
+        assert!((eval("atan2(1, 1) * 4 - acos(-1)".chars(), &mut state).unwrap()).abs() < 1e-12);
+        assert_eq!(eval("log10(1000) + log2(8) + log(exp(2))".chars(), &mut state), Ok(8.0));
+        assert_eq!(eval("log(0)".chars(), &mut state), Err(InvalidOperand { op: "log".to_string(), operand: "0".to_string(), span: Span::new(0, 6) }));
+    }
+
+    #[test]
+    fn host_functions() {
+        let mut state: State = State::new();
+        state.register_function("price", Arity::Exact(1), |args| match args[0] {
+            1 => Ok(250),
+            2 => Ok(99),
+            sku => Err(HostError::new(format!("unknown sku {}", sku))),
+        });
+        state.register_function("sum", Arity::AtLeast(0), |args| Ok(args.iter().sum()));
+        state.register_function("abs", Arity::Exact(1), |_| Ok(-1));
+
+        assert_eq!(eval("price(1) + 2 * price(2)".chars(), &mut state), Ok(448));
+        assert_eq!(eval("sum() + sum(1) + sum(1, 2, 3, max(4, 5))".chars(), &mut state), Ok(12));
+        assert_eq!(eval("abs(5)".chars(), &mut state), Ok(-1));
+        assert_eq!(eval("price(1, 2)".chars(), &mut state), Err(ArityMismatch { name: "price".to_string(), expected: Arity::Exact(1), found: 2, span: Span::new(0, 11) }));
+
+        let error = eval("1 + price(7)".chars(), &mut state).unwrap_err();
+        assert_eq!(error, Host { function: "price".to_string(), error: HostError::new("unknown sku 7"), span: Span::new(4, 12) });
+        assert_eq!(error.to_string(), "Error in price: unknown sku 7");
+        assert_eq!(std::error::Error::source(&error).unwrap().to_string(), "unknown sku 7");
+    }
+
+    #[test]
+    fn user_defined_functions() {
+        let mut state: State = State::new();
+
+        assert_eq!(eval("f(x, y) = x^2 + y".chars(), &mut state), Ok(0));
+        assert_eq!(eval("f(3, 4)".chars(), &mut state), Ok(13));
+        assert_eq!(eval("x = 10".chars(), &mut state), Ok(10));
+        assert_eq!(eval("f(x, 1) + x".chars(), &mut state), Ok(111));
+        assert_eq!(eval("y".chars(), &mut state), Err(UninitializedVariable("y".to_string(), Span::new(0, 1))));
+        assert_eq!(eval("g(x) = f(x, x) * scale".chars(), &mut state), Ok(0));
+        assert_eq!(eval("scale = 2".chars(), &mut state), Ok(2));
+        assert_eq!(eval("g(3)".chars(), &mut state), Ok(24));
+        assert_eq!(eval("abs(x) = 7".chars(), &mut state), Ok(0));
+        assert_eq!(eval("abs(-1)".chars(), &mut state), Ok(7));
+
+        assert_eq!(eval("f(1)".chars(), &mut state), Err(ArityMismatch { name: "f".to_string(), expected: Arity::Exact(2), found: 1, span: Span::new(0, 4) }));
+        assert_eq!(eval("h(x, 1) = x".chars(), &mut state), Err(InvalidParameter(Span::new(5, 6))));
+        assert_eq!(eval("h(x, x) = x".chars(), &mut state), Err(DuplicateParameter("x".to_string(), Span::new(5, 6))));
+        assert_eq!(eval("p() = y".chars(), &mut state), Ok(0));
+        assert_eq!(eval("q(y) = p()".chars(), &mut state), Ok(0));
+        assert_eq!(eval("q(5)".chars(), &mut state), Err(UninitializedVariable("y".to_string(), Span::new(0, 4))));
+
+        state.max_call_depth = 100;
+        assert_eq!(eval("loop(n) = loop(n + 1)".chars(), &mut state), Ok(0));
+        assert_eq!(eval("1 + loop(0)".chars(), &mut state), Err(RecursionLimit("loop".to_string(), Span::new(4, 11))));
+        assert_eq!(state.call_depth, 0);
+        assert_eq!(state.variables.get("n"), None);
+
+        assert_eq!(eval("inverse(x) = 1 / x".chars(), &mut state), Ok(0));
+        let error = eval("ΔΔΔΔΔΔ = inverse(0)".chars(), &mut state).unwrap_err();
+        assert_eq!(error.render("ΔΔΔΔΔΔ = inverse(0)"), "Division by zero\n1 | ΔΔΔΔΔΔ = inverse(0)\n  |          ^^^^^^^^^^");
+    }
+
+    #[test]
+    fn nesting_limit() {
+        // the default limit has to fit the stack of a spawned thread, whatever the expression nests
+        let nested = std::thread::Builder::new().stack_size(2 << 20).spawn(|| {
+            let mut state: State = State::new();
+            let body = format!("{}f(n - 1){}", "(1 * ".repeat(10), ")".repeat(10));
+            eval(format!("f(n) = n <= 0 ? 0 : 1 + {}", body).chars(), &mut state).unwrap();
+            assert_eq!(eval("f(10)".chars(), &mut state), Ok(10));
+            assert!(matches!(eval("f(1000)".chars(), &mut state), Err(NestingLimit(_))));
+
+            eval("id(x) = x".chars(), &mut state).unwrap();
+            let calls = format!("{}1{}", "id(abs(".repeat(200), "))".repeat(200));
+            assert!(matches!(eval(calls.chars(), &mut state), Err(NestingLimit(_))));
+            let parenthesis = format!("{}1{}", "-(".repeat(1000), ")".repeat(1000));
+            assert!(matches!(eval(parenthesis.chars(), &mut state), Err(NestingLimit(_))));
+            assert_eq!(state.nesting, 0);
+            assert_eq!(state.call_depth, 0);
+        });
+        nested.unwrap().join().unwrap();
+    }
+
+    #[test]
+    fn comparison_and_logic() {
+        let mut state: State = State::new();
+
+        assert_eq!(eval("1 + 1 == 2".chars(), &mut state), Ok(1));
+        assert_eq!(eval("2 * 3 != 6".chars(), &mut state), Ok(0));
+        assert_eq!(eval("(1 < 2) + (2 <= 2) + (3 > 4) + (4 >= 5)".chars(), &mut state), Ok(2));
+        assert_eq!(eval("1 < 2 == 2 < 3".chars(), &mut state), Ok(1));
+        assert_eq!(eval("!0 + !5 + !!7".chars(), &mut state), Ok(2));
+        assert_eq!(eval("!3! == 0".chars(), &mut state), Ok(1));
+        assert_eq!(eval("0 || 1 && 0".chars(), &mut state), Ok(0));
+        assert_eq!(eval("1 || 0 && 0".chars(), &mut state), Ok(1));
+        assert_eq!(eval("-2 && 3".chars(), &mut state), Ok(1));
+        assert_eq!(eval("flag = 3 > 2 && 2 > 1".chars(), &mut state), Ok(1));
+
+        assert_eq!(eval("0 && (x = 1)".chars(), &mut state), Ok(0));
+        assert_eq!(eval("1 || x = 1 / 0".chars(), &mut state), Ok(1));
+        assert_eq!(state.variables.get("x"), None);
+        assert_eq!(eval("1 && (x = 5)".chars(), &mut state), Ok(1));
+        assert_eq!(state.variables.get("x"), Some(5));
+        assert_eq!(parse::<i32>("!a || b == c").unwrap().to_string(), "((!a) || (b == c))");
+
+        let mut state = State::<f64>::new();
+        assert_eq!(eval("0.1 + 0.2 > 0.3 && 0.5 >= 0.5".chars(), &mut state), Ok(1.0));
+    }
+
+    #[test]
+    fn conditional() {
+        let mut state: State = State::new();
+
+        assert_eq!(eval("x = 12".chars(), &mut state), Ok(12));
+        assert_eq!(eval("x > 10 ? x * 2 : x".chars(), &mut state), Ok(24));
+        assert_eq!(eval("y = x < 10 ? 1 : x < 20 ? 2 : 3".chars(), &mut state), Ok(2));
+        assert_eq!(eval("1 ? 0 ? 5 : 6 : 7".chars(), &mut state), Ok(6));
+        assert_eq!(eval("max(x ? 1 : 2, 0 || 0 ? 3 : 4)".chars(), &mut state), Ok(4));
+        assert_eq!(parse::<i32>("a || b ? c = 1 : d + 1").unwrap().to_string(), "((a || b) ? (c = 1) : (d + 1))");
+
+        assert_eq!(eval("x == 12 ? (a = 1) : (b = 2)".chars(), &mut state), Ok(1));
+        assert_eq!(state.variables.get("a"), Some(1));
+        assert_eq!(state.variables.get("b"), None);
+        assert_eq!(eval("0 ? 1 / 0 : 5".chars(), &mut state), Ok(5));
+
+        assert_eq!(eval("fact(n) = n <= 1 ? 1 : n * fact(n - 1)".chars(), &mut state), Ok(0));
+        assert_eq!(eval("fact(10)".chars(), &mut state), Ok(3628800));
+
+        assert_eq!(parse::<i32>("1 ? 2").unwrap_err(), InvalidSyntax(MissingColon, Span::new(2, 3)));
+        assert_eq!(parse::<i32>("(1 ? 2) : 3").unwrap_err(), InvalidSyntax(MissingColon, Span::new(3, 4)));
+        assert_eq!(parse::<i32>("1 : 2").unwrap_err(), InvalidSyntax(UnexpectedToken, Span::new(2, 3)));
+        assert_eq!(parse::<i32>("1 ? : 2").unwrap_err(), InvalidSyntax(MissingOperand, Span::new(4, 5)));
+    }
+
+    #[test]
+    fn bitwise_operators() {
+        let mut state: State = State::new();
+
+        assert_eq!(eval("12 & 10 | 1".chars(), &mut state), Ok(9));
+        assert_eq!(eval("1 | 2 xor 3 & 6".chars(), &mut state), Ok(1));
+        assert_eq!(eval("~0 + ~-5".chars(), &mut state), Ok(3));
+        assert_eq!(eval("1 << 4 + 1".chars(), &mut state), Ok(32));
+        assert_eq!(eval("-16 >> 2".chars(), &mut state), Ok(-4));
+        assert_eq!(eval("mask = (1 << 3) | (1 << 5)".chars(), &mut state), Ok(40));
+        assert_eq!(eval("mask & 8 == 8".chars(), &mut state), Ok(0));
+        assert_eq!(eval("(mask & 8) == 8".chars(), &mut state), Ok(1));
+        assert_eq!(parse::<i32>("~a & b xor c").unwrap().to_string(), "(((~a) & b) xor c)");
+
+        assert_eq!(eval("1 << 32".chars(), &mut state), Err(Overflow { op: "<<".to_string(), lhs: Some("1".to_string()), rhs: "32".to_string(), span: Span::new(0, 7) }));
+        assert_eq!(eval("1 >> -1".chars(), &mut state), Err(InvalidOperand { op: ">>".to_string(), operand: "-1".to_string(), span: Span::new(5, 7) }));
+        assert_eq!(parse::<i32>("1 ~ 2").unwrap_err(), InvalidSyntax(UnexpectedToken, Span::new(2, 3)));
+        state.overflow = OverflowPolicy::Wrapping;
+        assert_eq!(eval("1 << 33".chars(), &mut state), Ok(2));
+        state.overflow = OverflowPolicy::Saturating;
+        assert_eq!(eval("(1 << 40) + (-5 >> 40)".chars(), &mut state), Ok(-1));
+
+        let mut state = State::<BigInt>::new();
+        assert_eq!(eval("(1 << 100) >> 98 | 1".chars(), &mut state), Ok(BigInt::from(5)));
+        let mut state = State::<f64>::new();
+        assert_eq!(eval("1.5 & 1".chars(), &mut state), Err(InvalidOperand { op: "&".to_string(), operand: "1".to_string(), span: Span::new(6, 7) }));
+    }
+
+    #[test]
+    fn radix_literals() {
+        let mut state: State = State::new();
+
+        assert_eq!(eval("0x1F + 0b101 + 0o17".chars(), &mut state), Ok(51));
+        assert_eq!(eval("1_000_000 - 0xFF_FF".chars(), &mut state), Ok(934465));
+        assert_eq!(eval("0xFFFFFFFF".chars(), &mut state), Ok(-1));
+        assert_eq!(eval("0x1e-5".chars(), &mut state), Ok(25));
+        assert_eq!(parse::<i32>("0x").unwrap_err(), InvalidSyntax(InvalidNumber, Span::new(0, 2)));
+        assert_eq!(parse::<i32>("0b102").unwrap_err(), InvalidSyntax(InvalidNumber, Span::new(0, 5)));
+        assert_eq!(parse::<i32>("1__0").unwrap_err(), InvalidSyntax(InvalidNumber, Span::new(0, 4)));
+        assert_eq!(parse::<i32>("10_").unwrap_err(), InvalidSyntax(InvalidNumber, Span::new(0, 3)));
+        assert_eq!(parse::<i32>("0x_1").unwrap_err(), InvalidSyntax(InvalidNumber, Span::new(0, 4)));
+        assert_eq!(parse::<i32>("0x1_0000_0000").unwrap_err(), InvalidSyntax(InvalidNumber, Span::new(0, 13)));
+
+        assert_eq!(eval("1_000.25 + 0x10".chars(), &mut State::<f64>::new()), Ok(1016.25));
+        assert_eq!(eval("0x1_0000_0000_0000_0000".chars(), &mut State::<BigInt>::new()), Ok(BigInt::from(2).pow(64)));
+        assert_eq!(eval("0b11 / 1_0".chars(), &mut State::<BigRational>::new()), Ok(BigRational::new(3.into(), 10.into())));
+
+        assert_eq!(255.display_radix(16), Some("0xFF".to_string()));
+        assert_eq!((-1).display_radix(2), Some(format!("0b{}", "1".repeat(32))));
+        assert_eq!(8.display_radix(8), Some("0o10".to_string()));
+        assert_eq!(8.display_radix(10), None);
+        assert_eq!(BigInt::from(-255).display_radix(16), Some("-0xFF".to_string()));
+        assert_eq!(2.5.display_radix(16), None);
+    }
+
+    #[test]
+    fn compound_assignment() {
+        let mut state: State = State::new();
+
+        assert_eq!(eval("x = 10".chars(), &mut state), Ok(10));
+        assert_eq!(eval("x += 5".chars(), &mut state), Ok(15));
+        assert_eq!(eval("x -= 1 + 2".chars(), &mut state), Ok(12));
+        assert_eq!(eval("x *= 2".chars(), &mut state), Ok(24));
+        assert_eq!(eval("(x) /= 5".chars(), &mut state), Ok(4));
+        assert_eq!(eval("y = x += 1".chars(), &mut state), Ok(5));
+        assert_eq!(state.variables.get("y"), Some(5));
+
+        assert_eq!(eval("x++ * 10 + x".chars(), &mut state), Ok(56));
+        assert_eq!(eval("++x * 10 + x".chars(), &mut state), Ok(77));
+        assert_eq!(eval("x-- + --x".chars(), &mut state), Ok(12));
+        assert_eq!(eval("x+++x".chars(), &mut state), Ok(11));
+        assert_eq!(eval("1 - -x".chars(), &mut state), Ok(7));
+        assert_eq!(parse::<i32>("a += b++ - --c").unwrap().to_string(), "(a += ((b++) - (--c)))");
+
+        assert_eq!(eval("1 += 2".chars(), &mut state), Err(CanOnlyAssignToVariable(Span::new(0, 1))));
+        assert_eq!(eval("(x + 1)++".chars(), &mut state), Err(InvalidSyntax(MissingOperand, Span::new(9, 9))));
+        assert_eq!(eval("--5 + 5--3 + 1++1 - 1---1".chars(), &mut state), Ok(13));
+        assert_eq!(eval("(x)++ + x--".chars(), &mut state), Ok(13));
+        assert_eq!(parse::<i32>("2--x").unwrap().to_string(), "(2 - (-x))");
+        assert_eq!(eval("x--1 + x++1 - x--(1) + x--√4 + (x)--1".chars(), &mut state), Ok(6 + 1 + 6 + 1 - 6 + 1 + 6 + 2 + 6 + 1));
+        assert_eq!(parse::<i32>("a--b").unwrap().to_string(), "(a - (-b))");
+        assert_eq!(parse::<i32>("a-- - b++ + a").unwrap().to_string(), "(((a--) - (b++)) + a)");
+        assert_eq!(eval("z += 1".chars(), &mut state), Err(UninitializedVariable("z".to_string(), Span::new(0, 1))));
+        assert_eq!(eval("x /= 0".chars(), &mut state), Err(DivisionByZero(Span::new(5, 6))));
+        assert_eq!(state.variables.get("x"), Some(6));
+
+        state.variables.set("max", i32::MAX);
+        assert_eq!(eval("max++".chars(), &mut state), Err(Overflow { op: "++".to_string(), lhs: None, rhs: i32::MAX.to_string(), span: Span::new(0, 3) }));
+        assert_eq!(state.variables.get("max"), Some(i32::MAX));
+    }
+
+    #[test]
+    fn programs() {
+        let mut state: State = State::new();
+
+        let source = "width = 4; height = 3\n\narea = width *\n  height\nf(x) = (x +\n 1)\nf(area);";
+        assert_eq!(eval_program(source.chars(), &mut state), Ok(Some(13)));
+        assert_eq!(state.variables.get("area"), Some(12));
+        assert_eq!(eval_program("; \n ;".chars(), &mut state), Ok(None));
+
+        let program = parse_program::<i32>("a = 1\nb = a ? 2\n : 3; a + b").unwrap();
+        assert_eq!(program.to_string(), "(a = 1); (b = (a ? 2 : 3)); (a + b)");
+        assert_eq!(program.eval_all(&mut state), Ok(vec![1, 2, 3]));
+
+        let source = "a = 1\nb = a / 0\nc = 3";
+        let error = eval_program(source.chars(), &mut state).unwrap_err();
+        assert_eq!(error, ProgramError { statement: 2, error: DivisionByZero(Span::new(14, 15)) });
+        assert_eq!(error.render(source), "Statement 2: Division by zero\n2 | b = a / 0\n  |         ^");
+        assert_eq!(state.variables.get("c"), None);
+
+        assert_eq!(parse_program::<i32>("1; 2 +; 3").unwrap_err(), ProgramError { statement: 2, error: InvalidSyntax(MissingOperand, Span::new(6, 6)) });
+        assert_eq!(parse_program::<i32>("1\n(2; 3)").unwrap_err(), ProgramError { statement: 2, error: InvalidSyntax(UnbalancedParenthesis, Span::new(2, 3)) });
+        assert_eq!(parse::<i32>("1; 2").unwrap_err(), InvalidSyntax(UnexpectedToken, Span::new(1, 2)));
+        assert_eq!(parse::<i32>("1\n+ 2").unwrap().to_string(), "(1 + 2)");
+    }
+
+    #[test]
+    fn comments() {
+        let mut state: State = State::new();
+
+        let source = "# shipping costs\nrate = 5 # per kg\nweight = /* in kg */ 3\n/* multi\n   line */\nrate * weight";
+        assert_eq!(eval_program(source.chars(), &mut state), Ok(Some(15)));
+        assert_eq!(eval("7 // 2 # floor division, not a comment".chars(), &mut state), Ok(3));
+        assert_eq!(eval("1 + /**/ 2 /* * 10 */".chars(), &mut state), Ok(3));
+        assert_eq!(eval_program("# only a comment".chars(), &mut state), Ok(None));
+
+        assert_eq!(parse::<i32>("1 + /* 2").unwrap_err(), InvalidSyntax(UnterminatedComment, Span::new(4, 8)));
+        assert_eq!(parse::<i32>("1 + # 2").unwrap_err(), InvalidSyntax(MissingOperand, Span::new(3, 3)));
+    }
+
+    #[test]
+    fn unicode_identifiers() {
+        let mut state: State = State::new();
+
+        assert_eq!(eval("total_cost = 40".chars(), &mut state), Ok(40));
+        assert_eq!(eval("_tmp1 = 2".chars(), &mut state), Ok(2));
+        assert_eq!(eval_program("Δt = 3; α = Δt * _tmp1".chars(), &mut state), Ok(Some(6)));
+        assert_eq!(eval("total_cost / α + größe".chars(), &mut state), Err(UninitializedVariable("größe".to_string(), Span::new(18, 25))));
+        assert_eq!(eval("√16 + √(α + 3)".chars(), &mut state), Ok(7));
+        assert_eq!(eval("-√4^2".chars(), &mut state), Ok(-4));
+        assert_eq!(eval("√-4".chars(), &mut state), Err(InvalidOperand { op: "√".to_string(), operand: "-4".to_string(), span: Span::new(3, 5) }));
+        assert_eq!(parse::<i32>("√x!").unwrap().to_string(), "(√(x!))");
+        assert_eq!(parse::<i32>("2 √ 3").unwrap_err(), InvalidSyntax(UnexpectedToken, Span::new(2, 5)));
+
+        let error = eval("1 + Δx".chars(), &mut state).unwrap_err();
+        assert_eq!(error.render("1 + Δx"), "Uninitialized variable: Δx\n1 | 1 + Δx\n  |     ^^");
+        assert_eq!(UninitializedVariable("x".to_string(), Span::new(3, 9)).render("ΔΔ"), "Uninitialized variable: x\n1 | ΔΔ\n  |  ^");
+    }
+
+    #[test]
+    fn constants() {
+        let mut state = State::<f64>::new();
+
+        assert_eq!(eval("pi".chars(), &mut state), Ok(std::f64::consts::PI));
+        assert_eq!(eval("tau - 2 * π + τ - 2 * pi".chars(), &mut state), Ok(0.0));
+        assert_eq!(eval("e".chars(), &mut state), Ok(std::f64::consts::E));
+        assert_eq!(eval("pi = 3".chars(), &mut state), Err(AssignToConstant("pi".to_string(), Span::new(0, 2))));
+        assert_eq!(eval("(e) += 1".chars(), &mut state), Err(AssignToConstant("e".to_string(), Span::new(1, 2))));
+        assert_eq!(eval("τ++".chars(), &mut state), Err(AssignToConstant("τ".to_string(), Span::new(0, 2))));
+        assert_eq!(eval("f(pi) = pi * 2".chars(), &mut state), Ok(0.0));
+        assert_eq!(eval("f(1) + pi".chars(), &mut state), Ok(2.0 + std::f64::consts::PI));
+
+        let mut state: State = State::new();
+        assert_eq!(eval("pi".chars(), &mut state), Err(UninitializedVariable("pi".to_string(), Span::new(0, 2))));
+        state.variables.set("limit", 1);
+        state.define_constant("limit", 10);
+        assert_eq!(state.variables.get("limit"), None);
+        assert_eq!(eval("limit = 5".chars(), &mut state), Err(AssignToConstant("limit".to_string(), Span::new(0, 5))));
+
+        assert_eq!(eval_program("const rate = 2 + 3; rate * 2".chars(), &mut state), Ok(Some(10)));
+        assert_eq!(eval("rate = 1".chars(), &mut state), Err(AssignToConstant("rate".to_string(), Span::new(0, 4))));
+        assert_eq!(eval("const rate = 1".chars(), &mut state), Err(AssignToConstant("rate".to_string(), Span::new(0, 14))));
+        assert_eq!(state.get("rate"), Some(5));
+        assert_eq!(parse::<i32>("const x = 1").unwrap().to_string(), "(const x = 1)");
+        assert_eq!(parse::<i32>("const x + 1").unwrap_err(), InvalidSyntax(InvalidDeclaration, Span::new(0, 11)));
+        assert_eq!(parse::<i32>("const f(x) = 1").unwrap_err(), InvalidSyntax(InvalidDeclaration, Span::new(0, 14)));
+        assert_eq!(parse::<i32>("1 + const x = 1").unwrap_err(), InvalidSyntax(UnexpectedToken, Span::new(4, 9)));
+
+        assert_eq!(eval_program("double(n) = n * 2; const n = 5; double(3) + n".chars(), &mut state), Ok(Some(11)));
+    }
+
+    #[test]
+    fn variable_resolver() {
+        /// Reads fall back to shared defaults, writes only go to the overrides
+        #[derive(Debug, Clone)]
+        struct Layered {
+            defaults: std::sync::Arc<std::collections::HashMap<String, i32>>,
+            overrides: std::collections::HashMap<String, i32>,
+        }
+
+        impl VariableResolver<i32> for Layered {
+            fn get(&self, name: &str) -> Option<i32> {
+                self.overrides.get(name).or_else(|| self.defaults.get(name)).copied()
+            }
+
+            fn set(&mut self, name: &str, value: i32) {
+                self.overrides.insert(name.to_string(), value);
+            }
+
+            fn remove(&mut self, name: &str) -> Option<i32> {
+                self.overrides.remove(name)
+            }
+        }
+
+        let defaults = std::sync::Arc::new(std::collections::HashMap::from([("limit".to_string(), 10), ("x".to_string(), 1)]));
+        let mut state = State::with_variables(Layered { defaults: defaults.clone(), overrides: Default::default() });
+
+        assert_eq!(eval("limit * 2".chars(), &mut state), Ok(20));
+        assert_eq!(eval("limit += 5".chars(), &mut state), Ok(15));
+        assert_eq!(eval_program("f(x) = x + limit; f(2) + x".chars(), &mut state), Ok(Some(18)));
+        assert!(state.variables.contains("limit"));
+        assert!(!state.variables.contains("missing"));
+        assert_eq!(defaults.get("limit"), Some(&10));
+
+        let mut copy = state.clone();
+        assert_eq!(eval("limit = 0".chars(), &mut copy), Ok(0));
+        assert_eq!(state.get("limit"), Some(15));
+    }
+
+    #[test]
+    fn scopes() {
+        let mut state: State = State::new();
+        eval_program("x = 1; y = 2".chars(), &mut state).unwrap();
+
+        state.push_scope();
+        assert_eq!(eval("x = 10".chars(), &mut state), Ok(10));
+        assert_eq!(eval("z = x + y".chars(), &mut state), Ok(12));
+        state.push_scope();
+        assert_eq!(eval("x += 5".chars(), &mut state), Ok(15));
+        assert_eq!(state.scope_depth(), 2);
+        assert_eq!(state.pop_scope(), Some(std::collections::HashMap::from([("x".to_string(), 15)])));
+        assert_eq!(eval("x + z".chars(), &mut state), Ok(22));
+        state.pop_scope();
+        assert_eq!(state.pop_scope(), None);
+        assert_eq!(eval("x".chars(), &mut state), Ok(1));
+        assert_eq!(eval("z".chars(), &mut state), Err(UninitializedVariable("z".to_string(), Span::new(0, 1))));
+
+        state.assignment = AssignmentScope::NearestBinding;
+        state.push_scope();
+        state.push_scope_with(std::collections::HashMap::from([("y".to_string(), 20)]));
+        state.push_scope();
+        assert_eq!(eval_program("x = 3; y = 30; w = 4".chars(), &mut state), Ok(Some(4)));
+        assert_eq!(state.pop_scope(), Some(std::collections::HashMap::from([("w".to_string(), 4)])));
+        assert_eq!(state.pop_scope(), Some(std::collections::HashMap::from([("y".to_string(), 30)])));
+        state.pop_scope();
+        assert_eq!(eval("x * 10 + y".chars(), &mut state), Ok(32));
+
+        assert_eq!(eval_program("count = 0; inc(n) = count += n; inc(2) + inc(3)".chars(), &mut state), Ok(Some(7)));
+        assert_eq!(state.get("count"), Some(5));
+        assert_eq!(eval_program("inner() = y = 100; outer(y) = inner() + y; outer(1)".chars(), &mut state), Ok(Some(101)));
+        assert_eq!(state.get("y"), Some(100));
+        state.assignment = AssignmentScope::Innermost;
+        assert_eq!(eval("inc(1)".chars(), &mut state), Ok(6));
+        assert_eq!(state.get("count"), Some(6));
+        assert_eq!(eval_program("c = 0; bump() = c += 1; bump(); bump()".chars(), &mut state), Ok(Some(2)));
+        assert_eq!(state.get("c"), Some(2));
+        assert_eq!(eval_program("set(n) = n = 10; v = 1; set(v) + v".chars(), &mut state), Ok(Some(11)));
+        assert_eq!(state.scope_depth(), 0);
+    }
+
+    #[test]
+    fn atomic_evaluation() {
+        let mut state: State = State::new();
+        state.variables.set("a", 1);
+
+        assert_eq!(eval_atomic("a = 5 + (b = 1) / 0".chars(), &mut state), Err(DivisionByZero(Span::new(18, 19))));
+        assert_eq!(state.get("a"), Some(1));
+        assert_eq!(state.get("b"), None);
+        assert_eq!(eval("(b = 1) / 0".chars(), &mut state), Err(DivisionByZero(Span::new(10, 11))));
+        assert_eq!(state.get("b"), Some(1));
+
+        assert_eq!(eval_atomic("a = (c = 2) + a".chars(), &mut state), Ok(3));
+        assert_eq!(state.variables.get("a"), Some(3));
+        assert_eq!(state.variables.get("c"), Some(2));
+
+        let program = parse_program::<i32>("const k = 4; f(x) = x * k; a = f(a); a / 0").unwrap();
+        assert!(program.eval_atomic(&mut state).is_err());
+        assert_eq!(state.get("a"), Some(3));
+        assert!(!state.constants.contains_key("k"));
+        assert!(!state.definitions.contains_key("f"));
+
+        let snapshot = state.snapshot();
+        eval_program("a = 100; const c = 7; g(x) = x".chars(), &mut state).unwrap();
+        assert_eq!(state.get("c"), Some(7));
+        state.restore(snapshot);
+        assert_eq!(state.get("a"), Some(3));
+        assert_eq!(state.get("c"), Some(2));
+        assert!(state.definitions.is_empty());
+    }
+
+    #[test]
+    fn save_and_load() {
+        let mut state: State = State::new();
+        state.overflow = OverflowPolicy::Wrapping;
+        state.remainder = RemainderMode::Euclidean;
+        state.max_call_depth = 16;
+        state.variables.set("min", i32::MIN);
+        eval_program("x = 5; const k = 3; f(n, m) = n > 1 ? n * f(n - 1, m) : -m ^ 2; g() = x++ + k".chars(), &mut state).unwrap();
+
+        let saved = state.save();
+        let mut loaded: State = State::new();
+        loaded.variables.set("stale", 1);
+        loaded.register_function("host", Arity::Exact(0), |_| Ok(7));
+        assert_eq!(loaded.load(&saved), Ok(()));
+        assert_eq!(loaded.save(), saved);
+        assert_eq!(loaded.get("stale"), None);
+        assert_eq!(loaded.overflow, OverflowPolicy::Wrapping);
+        assert_eq!(loaded.remainder, RemainderMode::Euclidean);
+        assert_eq!(loaded.max_call_depth, 16);
+        assert_eq!(eval("min - 1".chars(), &mut loaded), Ok(i32::MAX));
+        assert_eq!(eval("f(4, 2) + g() + x + host()".chars(), &mut loaded), Ok(-96 + 8 + 6 + 7));
+        assert_eq!(eval("k = 1".chars(), &mut loaded), Err(AssignToConstant("k".to_string(), Span::new(0, 1))));
+
+        let mut rational: State<BigRational> = State::new();
+        eval_program("third = 1/3; h(a) = a / 4 + 0.5".chars(), &mut rational).unwrap();
+        let mut copy: State<BigRational> = State::new();
+        copy.load(&rational.save()).unwrap();
+        assert_eq!(eval("h(third) * 12".chars(), &mut copy).unwrap().to_string(), "7");
+        eval("half(x) = x / 0.5".chars(), &mut rational).unwrap();
+        copy.load(&rational.save()).unwrap();
+        copy.load(&copy.save()).unwrap();
+        assert_eq!(eval("half(1)".chars(), &mut copy).unwrap().to_string(), "2");
+
+        let mut bits: State = State::new();
+        eval("k(x) = 0xFFFFFFFF ^ 2 + x".chars(), &mut bits).unwrap();
+        let mut copy: State = State::new();
+        copy.load(&bits.save()).unwrap();
+        assert_eq!(eval("k(0)".chars(), &mut copy), Ok(1));
+
+        let mut float: State<f64> = State::new();
+        eval("huge(x) = x + 1e400".chars(), &mut float).unwrap();
+        let mut copy: State<f64> = State::new();
+        copy.load(&float.save()).unwrap();
+        assert_eq!(eval("huge(1)".chars(), &mut copy), Ok(f64::INFINITY));
+
+        assert_eq!(loaded.load(&rational.save()), Err(LoadError::InvalidValue { name: "third".to_string(), value: "1/3".to_string() }));
+        assert!(matches!(loaded.load("x = 1"), Err(LoadError::Format(_))));
+        assert_eq!(loaded.load(&saved.replace("\"version\": 1", "\"version\": 2")), Err(LoadError::UnsupportedVersion(2)));
+        assert!(matches!(loaded.load(&saved.replace("(x++)", "(x+)")), Err(LoadError::InvalidFunction { .. })));
+        assert_eq!(loaded.load(&saved.replace("\"m\"", "\"n\"")),
+                   Err(LoadError::InvalidFunction { name: "f".to_string(), error: DuplicateParameter("n".to_string(), Span::new(0, 1)) }));
+        assert_eq!(loaded.load(&saved.replace("\"m\"", "\"m + 1\"")),
+                   Err(LoadError::InvalidFunction { name: "f".to_string(), error: InvalidParameter(Span::new(0, 5)) }));
+        assert_eq!(loaded.get("x"), Some(6));
+        assert_eq!(loaded.load(&saved.replace("\"max_call_depth\": 16", "\"max_call_depth\": 100000")), Ok(()));
+        assert_eq!(loaded.max_call_depth, loaded.max_nesting);
+    }
+
+    #[test]
+    fn reactive_formulas() {
+        let mut sheet: Sheet = Sheet::new();
+        sheet.set_value("price", 10).unwrap();
+        assert_eq!(sheet.set("qty = 3"), Ok(3));
+        assert_eq!(sheet.set("total = price * qty"), Ok(30));
+        assert_eq!(sheet.set("discount = total >= 50 ? 5 : 0"), Ok(0));
+        assert_eq!(sheet.set("due = total - discount"), Ok(30));
+        assert_eq!(sheet.dependencies("due"), ["total", "discount"]);
+
+        sheet.set_value("price", 20).unwrap();
+        assert_eq!(sheet.get("total"), Some(60));
+        assert_eq!(sheet.get("discount"), Some(5));
+        assert_eq!(sheet.get("due"), Some(55));
+        assert_eq!(sheet.set("qty = 1 + 1"), Ok(2));
+        assert_eq!(sheet.get("due"), Some(40));
+
+        assert_eq!(sheet.set("qty = due"), Err(CyclicFormula(vec!["qty".to_string(), "due".to_string(), "total".to_string(), "qty".to_string()], Span::new(6, 9))));
+        assert_eq!(sheet.set("n = n + 1"), Err(CyclicFormula(vec!["n".to_string(), "n".to_string()], Span::new(4, 5))));
+        assert_eq!(sheet.set("f(x) = x * due"), Err(InvalidSyntax(InvalidFormula, Span::new(0, 14))));
+        assert_eq!(sheet.set("qty"), Err(InvalidSyntax(InvalidFormula, Span::new(0, 3))));
+        assert_eq!(sheet.set("x = price++"), Err(InvalidSyntax(AssignmentInFormula, Span::new(4, 11))));
+        assert_eq!(sheet.set("x = 1 + (price = 2)"), Err(InvalidSyntax(AssignmentInFormula, Span::new(9, 18))));
+        assert_eq!(sheet.set("x = qty * (price += 1)"), Err(InvalidSyntax(AssignmentInFormula, Span::new(11, 21))));
+        assert_eq!(sheet.set("x = --qty"), Err(InvalidSyntax(AssignmentInFormula, Span::new(4, 9))));
+        assert_eq!(sheet.get("price"), Some(20));
+        assert_eq!(sheet.set("x = qty--1"), Ok(3));
+
+        assert_eq!(sheet.set_value("price", 0), Ok(()));
+        assert_eq!(sheet.set("ratio = due / total"), Err(DivisionByZero(Span::new(14, 19))));
+        assert_eq!(sheet.formula("ratio").map(ToString::to_string), None);
+        assert_eq!(sheet.set("qty = 1 / price"), Err(DivisionByZero(Span::new(10, 15))));
+        assert_eq!(sheet.get("qty"), Some(2));
+        assert_eq!(sheet.formula("qty").unwrap().to_string(), "(qty = (1 + 1))");
+
+        let mut state: State = State::new();
+        eval_program("tax(x) = x * rate / 100; bump() = rate += 1".chars(), &mut state).unwrap();
+        let mut sheet = Sheet::with_state(state);
+        sheet.set_value("rate", 10).unwrap();
+        sheet.set_value("net", 200).unwrap();
+        assert_eq!(sheet.set("gross = net + tax(net)"), Ok(220));
+        assert_eq!(sheet.dependencies("gross"), ["net", "rate"]);
+        assert_eq!(sheet.set("bumped = net + bump()"), Err(InvalidSyntax(AssignmentInFormula, Span::new(15, 21))));
+        sheet.set_value("rate", 20).unwrap();
+        assert_eq!(sheet.get("gross"), Some(240));
+        assert_eq!(sheet.set("rate = gross"), Err(CyclicFormula(vec!["rate".to_string(), "gross".to_string(), "rate".to_string()], Span::new(7, 12))));
+
+        /// Drops every write
+        #[derive(Debug, Clone)]
+        struct ReadOnly;
+
+        impl VariableResolver<i32> for ReadOnly {
+            fn get(&self, _: &str) -> Option<i32> {
+                None
+            }
+
+            fn set(&mut self, _: &str, _: i32) {}
+
+            fn remove(&mut self, _: &str) -> Option<i32> {
+                None
+            }
+        }
+
+        let mut sheet = Sheet::with_state(State::with_variables(ReadOnly));
+        assert_eq!(sheet.set("a = 1"), Ok(1));
+        assert_eq!(sheet.get("a"), None);
+    }
+}
@@ -1,4 +1,6 @@
 use std::iter::Peekable;
+use unicode_xid::UnicodeXID;
+use crate::span::Span;
 
 #[derive(Debug, Clone, PartialEq, Eq, Hash)]
 pub enum Token {
@@ -6,13 +8,24 @@ pub enum Token {
     Operation(OperationToken),
     OpenParenthesis,
     CloseParenthesis,
+    Comma,
+    /// The `const` keyword
+    Const,
+    /// Separates the branches of a conditional, `c ? a : b`
+    Colon,
+    Semicolon,
+    /// A line break, which separates statements unless the expression continues on the next line
+    Newline,
+    /// A `/*` comment without the closing `*/`
+    UnterminatedComment,
     Unexpected,
 }
 
 #[derive(Debug, Clone, PartialEq, Eq, Hash)]
 pub enum OperandsToken {
     Variable(String),
-    Constant(i32),
+    /// Text of a number literal, converted to a value by the parser
+    Constant(String),
 }
 
 #[derive(Debug, Clone, PartialEq, Eq, Hash)]
@@ -21,66 +34,192 @@ pub enum OperationToken {
     Minus,
     Multiply,
     Divide,
+    FloorDivide,
+    Remainder,
+    Power,
     Assign,
+    /// `!`, postfix factorial or prefix logical not
+    Exclamation,
+    Equal,
+    NotEqual,
+    Less,
+    LessEqual,
+    Greater,
+    GreaterEqual,
+    And,
+    Or,
+    Question,
+    BitAnd,
+    BitOr,
+    Xor,
+    /// `~`, bitwise not
+    Tilde,
+    ShiftLeft,
+    ShiftRight,
+    /// `√`, prefix square root
+    SquareRoot,
+    PlusAssign,
+    MinusAssign,
+    MultiplyAssign,
+    DivideAssign,
+    /// `++`, prefix or postfix
+    Increment,
+    /// `--`, prefix or postfix
+    Decrement,
 }
 
-/// An iterator wrapper for iterator of `char`, essentially a lexer
+/// An iterator wrapper for iterator of `char`, essentially a lexer.
+/// Every token is yielded together with its byte span in the source.
 pub(crate) struct TokenIterator<T>
     where
         T: Iterator<Item = char>
 {
-    pub(crate) inner: Peekable<T>,
+    inner: Peekable<T>,
+    offset: usize,
+}
+
+impl<T> TokenIterator<T>
+    where
+        T: Iterator<Item = char>
+{
+    pub(crate) fn new(inner: T) -> Self {
+        TokenIterator { inner: inner.peekable(), offset: 0 }
+    }
+
+    /// Reads the rest of a number literal such as `42`, `3.14`, `.5`, `1e-9`, `0x1F` or `1_000`.
+    /// Trailing letters are kept so that `12abc` is reported as a single invalid literal.
+    fn number(&mut self, first: char) -> String {
+        let mut literal = String::from(first);
+        while let Some(&c) = self.inner.peek() {
+            let prefixed = first == '0' && literal[1..].starts_with(['x', 'X', 'b', 'B', 'o', 'O']);
+            let exponent_sign = matches!(c, '+' | '-') && literal.ends_with(['e', 'E']) && !prefixed;
+            if c.is_ascii_alphanumeric() || c == '.' || c == '_' || exponent_sign {
+                literal.push(c);
+                self.bump();
+            } else {
+                break;
+            }
+        }
+        literal
+    }
+
+    /// Skips the rest of a `/* */` comment, `false` if the input ends before it is closed
+    fn block_comment(&mut self) -> bool {
+        while let Some(c) = self.bump() {
+            if c == '*' && self.bump_if('/') {
+                return true;
+            }
+        }
+        false
+    }
+
+    /// Consumes the next char only if it is the expected one
+    fn bump_if(&mut self, expected: char) -> bool {
+        if self.inner.peek() == Some(&expected) {
+            self.bump();
+            true
+        } else {
+            false
+        }
+    }
+
+    /// Consumes the next char, keeping track of the byte offset
+    fn bump(&mut self) -> Option<char> {
+        let c = self.inner.next()?;
+        self.offset += c.len_utf8();
+        Some(c)
+    }
 }
 
 impl<T> Iterator for TokenIterator<T>
     where
         T: Iterator<Item = char>
 {
-    type Item = Token;
+    type Item = (Token, Span);
 
     fn next(&mut self) -> Option<Self::Item> {
         loop {
-            return match self.inner.next() {
+            let start = self.offset;
+            let token = match self.bump() {
                 Some(c) => {
-                    if c.is_ascii_whitespace() {
+                    if c.is_ascii_whitespace() && c != '\n' {
                         continue;
                     }
                     match c {
-                        '+' => Some(Token::Operation(OperationToken::Plus)),
-                        '-' => Some(Token::Operation(OperationToken::Minus)),
-                        '*' => Some(Token::Operation(OperationToken::Multiply)),
-                        '/' => Some(Token::Operation(OperationToken::Divide)),
-                        '(' => Some(Token::OpenParenthesis),
-                        ')' => Some(Token::CloseParenthesis),
-                        '=' => Some(Token::Operation(OperationToken::Assign)),
-                        '0'..='9' | 'a'..='z' | 'A'..='Z' => {
+                        '\n' => Token::Newline,
+                        ';' => Token::Semicolon,
+                        '+' if self.bump_if('+') => Token::Operation(OperationToken::Increment),
+                        '+' if self.bump_if('=') => Token::Operation(OperationToken::PlusAssign),
+                        '+' => Token::Operation(OperationToken::Plus),
+                        '-' if self.bump_if('-') => Token::Operation(OperationToken::Decrement),
+                        '-' if self.bump_if('=') => Token::Operation(OperationToken::MinusAssign),
+                        '-' => Token::Operation(OperationToken::Minus),
+                        '*' if self.bump_if('*') => Token::Operation(OperationToken::Power),
+                        '*' if self.bump_if('=') => Token::Operation(OperationToken::MultiplyAssign),
+                        '*' => Token::Operation(OperationToken::Multiply),
+                        '^' => Token::Operation(OperationToken::Power),
+                        '#' => {
+                            // the line break itself still separates statements
+                            while self.inner.peek().is_some_and(|c| *c != '\n') {
+                                self.bump();
+                            }
+                            continue;
+                        }
+                        '/' if self.bump_if('*') => {
+                            if self.block_comment() {
+                                continue;
+                            }
+                            Token::UnterminatedComment
+                        }
+                        '/' if self.bump_if('/') => Token::Operation(OperationToken::FloorDivide),
+                        '/' if self.bump_if('=') => Token::Operation(OperationToken::DivideAssign),
+                        '/' => Token::Operation(OperationToken::Divide),
+                        '%' => Token::Operation(OperationToken::Remainder),
+                        '(' => Token::OpenParenthesis,
+                        ')' => Token::CloseParenthesis,
+                        ',' => Token::Comma,
+                        ':' => Token::Colon,
+                        '?' => Token::Operation(OperationToken::Question),
+                        '=' if self.bump_if('=') => Token::Operation(OperationToken::Equal),
+                        '=' => Token::Operation(OperationToken::Assign),
+                        '!' if self.bump_if('=') => Token::Operation(OperationToken::NotEqual),
+                        '!' => Token::Operation(OperationToken::Exclamation),
+                        '<' if self.bump_if('<') => Token::Operation(OperationToken::ShiftLeft),
+                        '<' if self.bump_if('=') => Token::Operation(OperationToken::LessEqual),
+                        '<' => Token::Operation(OperationToken::Less),
+                        '>' if self.bump_if('>') => Token::Operation(OperationToken::ShiftRight),
+                        '>' if self.bump_if('=') => Token::Operation(OperationToken::GreaterEqual),
+                        '>' => Token::Operation(OperationToken::Greater),
+                        '&' if self.bump_if('&') => Token::Operation(OperationToken::And),
+                        '&' => Token::Operation(OperationToken::BitAnd),
+                        '|' if self.bump_if('|') => Token::Operation(OperationToken::Or),
+                        '|' => Token::Operation(OperationToken::BitOr),
+                        '~' => Token::Operation(OperationToken::Tilde),
+                        '√' => Token::Operation(OperationToken::SquareRoot),
+                        '.' if !self.inner.peek().is_some_and(char::is_ascii_digit) => Token::Unexpected,
+                        '0'..='9' | '.' => Token::Operand(OperandsToken::Constant(self.number(c))),
+                        c if c == '_' || c.is_xid_start() => {
                             let mut word = String::from(c);
-                            let constant = match c {
-                                '0'..='9' => true,
-                                _ => false,
-                            };
                             while let Some(c) = self.inner.peek() {
-                                if c.is_ascii_alphanumeric() {
+                                if c.is_xid_continue() {
                                     word.push(*c);
-                                    self.inner.next();
+                                    self.bump();
                                 } else {
                                     break;
                                 }
                             }
-                            if constant {
-                                match word.parse() {
-                                    Ok(number) => Some(Token::Operand(OperandsToken::Constant(number))),
-                                    Err(_) => Some(Token::Unexpected),
-                                }
-                            } else {
-                                Some(Token::Operand(OperandsToken::Variable(word)))
+                            match word.as_str() {
+                                "xor" => Token::Operation(OperationToken::Xor),
+                                "const" => Token::Const,
+                                _ => Token::Operand(OperandsToken::Variable(word)),
                             }
                         },
-                        _ => Some(Token::Unexpected),
+                        _ => Token::Unexpected,
                     }
                 },
-                None => None,
-            }
+                None => return None,
+            };
+            return Some((token, Span::new(start, self.offset)));
         }
     }
-}
\ No newline at end of file
+}
@@ -0,0 +1,747 @@
+use std::fmt::{Debug, Display};
+use std::str::FromStr;
+use num_traits::{FromPrimitive, One, Signed, ToPrimitive, Zero};
+use crate::state::OverflowPolicy;
+
+pub use num_bigint::BigInt;
+pub use num_rational::BigRational;
+
+/// Why an arithmetic operation could not produce a value
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum ArithmeticError {
+    Overflow,
+    DivisionByZero,
+    InvalidOperand,
+}
+
+/// A number type the evaluator can compute with
+pub trait Value: Clone + Debug + Display + PartialOrd + Send + Sync + 'static {
+    /// Converts the text of a number literal, `None` if it does not denote a value of this type
+    fn parse_literal(literal: &str) -> Option<Self>;
+    /// Reads the value back from the text written by its `Display` implementation, used by `State::load`
+    fn parse_saved(text: &str) -> Option<Self> {
+        Self::parse_literal(text)
+    }
+    fn from_i32(value: i32) -> Self;
+    /// Closest value of this type, integers truncate toward zero.
+    /// `None` if the value is not finite or out of range.
+    fn from_f64(value: f64) -> Option<Self>;
+    fn to_f64(&self) -> f64;
+    fn is_integer(&self) -> bool;
+
+    fn negate(&self, overflow: OverflowPolicy) -> Result<Self, ArithmeticError>;
+    fn add(&self, rhs: &Self, overflow: OverflowPolicy) -> Result<Self, ArithmeticError>;
+    fn subtract(&self, rhs: &Self, overflow: OverflowPolicy) -> Result<Self, ArithmeticError>;
+    fn multiply(&self, rhs: &Self, overflow: OverflowPolicy) -> Result<Self, ArithmeticError>;
+    fn divide(&self, rhs: &Self, overflow: OverflowPolicy) -> Result<Self, ArithmeticError>;
+    /// Division rounding toward negative infinity
+    fn floor_divide(&self, rhs: &Self, overflow: OverflowPolicy) -> Result<Self, ArithmeticError>;
+    /// Remainder with the sign of the dividend
+    fn remainder(&self, rhs: &Self, overflow: OverflowPolicy) -> Result<Self, ArithmeticError>;
+    /// Remainder that is never negative
+    fn remainder_euclid(&self, rhs: &Self, overflow: OverflowPolicy) -> Result<Self, ArithmeticError>;
+    fn power(&self, rhs: &Self, overflow: OverflowPolicy) -> Result<Self, ArithmeticError>;
+    fn factorial(&self, overflow: OverflowPolicy) -> Result<Self, ArithmeticError>;
+    fn abs(&self, overflow: OverflowPolicy) -> Result<Self, ArithmeticError>;
+    /// Square root, rounded toward zero for integers
+    fn sqrt(&self) -> Result<Self, ArithmeticError>;
+
+    /// Rounds toward negative infinity, types that cannot hold fractions are returned unchanged
+    fn floor(&self) -> Self {
+        self.clone()
+    }
+
+    /// Rounds toward positive infinity, types that cannot hold fractions are returned unchanged
+    fn ceil(&self) -> Self {
+        self.clone()
+    }
+
+    /// Rounds half-way cases away from zero, types that cannot hold fractions are returned unchanged
+    fn round(&self) -> Self {
+        self.clone()
+    }
+
+    /// Bitwise operations are only defined for integer types
+    fn bit_and(&self, _rhs: &Self, _overflow: OverflowPolicy) -> Result<Self, ArithmeticError> {
+        Err(ArithmeticError::InvalidOperand)
+    }
+
+    fn bit_or(&self, _rhs: &Self, _overflow: OverflowPolicy) -> Result<Self, ArithmeticError> {
+        Err(ArithmeticError::InvalidOperand)
+    }
+
+    fn bit_xor(&self, _rhs: &Self, _overflow: OverflowPolicy) -> Result<Self, ArithmeticError> {
+        Err(ArithmeticError::InvalidOperand)
+    }
+
+    fn bit_not(&self, _overflow: OverflowPolicy) -> Result<Self, ArithmeticError> {
+        Err(ArithmeticError::InvalidOperand)
+    }
+
+    /// Shifting by a negative amount is invalid
+    fn shift_left(&self, _rhs: &Self, _overflow: OverflowPolicy) -> Result<Self, ArithmeticError> {
+        Err(ArithmeticError::InvalidOperand)
+    }
+
+    /// Arithmetic shift that keeps the sign, shifting by a negative amount is invalid
+    fn shift_right(&self, _rhs: &Self, _overflow: OverflowPolicy) -> Result<Self, ArithmeticError> {
+        Err(ArithmeticError::InvalidOperand)
+    }
+
+    /// `1` for true and `0` for false
+    fn from_bool(value: bool) -> Self {
+        Self::from_i32(value as i32)
+    }
+
+    /// Every value except zero is true
+    fn is_truthy(&self) -> bool {
+        *self != Self::from_i32(0)
+    }
+
+    /// Formats the value in base 2, 8 or 16 with the matching prefix, `None` for types that can hold fractions
+    fn display_radix(&self, _radix: u32) -> Option<String> {
+        None
+    }
+
+    /// Formats the value in the given style, types that cannot hold fractions ignore it
+    fn display_as(&self, _style: FractionStyle) -> String {
+        self.to_string()
+    }
+}
+
+/// How values with a fractional part are printed
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum FractionStyle {
+    /// `7/2`
+    Fraction,
+    /// `3 1/2`
+    Mixed,
+    /// `3.5`, rounded to the given number of digits after the point
+    Decimal(usize),
+}
+
+/// Splits a number literal into its radix and digits, removing the `0x`, `0b` or `0o` prefix
+/// and the `_` separators. `None` if a separator is not placed between two digits.
+fn literal_digits(literal: &str) -> Option<(u32, String)> {
+    let (radix, digits) = match literal.get(..2) {
+        Some("0x" | "0X") => (16, &literal[2..]),
+        Some("0b" | "0B") => (2, &literal[2..]),
+        Some("0o" | "0O") => (8, &literal[2..]),
+        _ => (10, literal),
+    };
+    let chars: Vec<char> = digits.chars().collect();
+    for (i, c) in chars.iter().enumerate() {
+        let is_digit = |c: Option<&char>| c.is_some_and(|c| c.is_digit(radix));
+        if *c == '_' && !(i > 0 && is_digit(chars.get(i - 1)) && is_digit(chars.get(i + 1))) {
+            return None;
+        }
+    }
+    Some((radix, digits.replace('_', "")))
+}
+
+/// Applies the checked, wrapping or saturating version of an operation
+fn with_policy<T>(overflow: OverflowPolicy, checked: impl FnOnce() -> Option<T>, wrapping: impl FnOnce() -> T, saturating: impl FnOnce() -> T) -> Result<T, ArithmeticError> {
+    match overflow {
+        OverflowPolicy::Error => checked().ok_or(ArithmeticError::Overflow),
+        OverflowPolicy::Wrapping => Ok(wrapping()),
+        OverflowPolicy::Saturating => Ok(saturating()),
+    }
+}
+
+/// Integer arithmetic, division truncates toward zero
+impl Value for i32 {
+    /// Hexadecimal, binary and octal literals denote the bits of the value, so `0xFFFFFFFF` is `-1`
+    fn parse_literal(literal: &str) -> Option<Self> {
+        match literal_digits(literal)? {
+            (10, digits) => digits.parse().ok(),
+            (radix, digits) => u32::from_str_radix(&digits, radix).ok().map(|bits| bits as i32),
+        }
+    }
+
+    fn parse_saved(text: &str) -> Option<Self> {
+        text.parse().ok()
+    }
+
+    fn from_i32(value: i32) -> Self {
+        value
+    }
+
+    fn from_f64(value: f64) -> Option<Self> {
+        FromPrimitive::from_f64(value.trunc())
+    }
+
+    fn to_f64(&self) -> f64 {
+        f64::from(*self)
+    }
+
+    fn is_integer(&self) -> bool {
+        true
+    }
+
+    fn negate(&self, overflow: OverflowPolicy) -> Result<Self, ArithmeticError> {
+        with_policy(overflow, || self.checked_neg(), || self.wrapping_neg(), || self.saturating_neg())
+    }
+
+    fn add(&self, rhs: &Self, overflow: OverflowPolicy) -> Result<Self, ArithmeticError> {
+        with_policy(overflow, || self.checked_add(*rhs), || self.wrapping_add(*rhs), || self.saturating_add(*rhs))
+    }
+
+    fn subtract(&self, rhs: &Self, overflow: OverflowPolicy) -> Result<Self, ArithmeticError> {
+        with_policy(overflow, || self.checked_sub(*rhs), || self.wrapping_sub(*rhs), || self.saturating_sub(*rhs))
+    }
+
+    fn multiply(&self, rhs: &Self, overflow: OverflowPolicy) -> Result<Self, ArithmeticError> {
+        with_policy(overflow, || self.checked_mul(*rhs), || self.wrapping_mul(*rhs), || self.saturating_mul(*rhs))
+    }
+
+    fn divide(&self, rhs: &Self, overflow: OverflowPolicy) -> Result<Self, ArithmeticError> {
+        if *rhs == 0 {
+            return Err(ArithmeticError::DivisionByZero);
+        }
+        with_policy(overflow, || self.checked_div(*rhs), || self.wrapping_div(*rhs), || self.saturating_div(*rhs))
+    }
+
+    fn floor_divide(&self, rhs: &Self, overflow: OverflowPolicy) -> Result<Self, ArithmeticError> {
+        let quotient = self.divide(rhs, overflow)?;
+        if self.wrapping_rem(*rhs) != 0 && (*self < 0) != (*rhs < 0) {
+            Ok(quotient - 1)
+        } else {
+            Ok(quotient)
+        }
+    }
+
+    // `i32::MIN % -1` only overflows in the intermediate division, the remainder itself is `0`
+    fn remainder(&self, rhs: &Self, _: OverflowPolicy) -> Result<Self, ArithmeticError> {
+        if *rhs == 0 {
+            return Err(ArithmeticError::DivisionByZero);
+        }
+        Ok(self.wrapping_rem(*rhs))
+    }
+
+    fn remainder_euclid(&self, rhs: &Self, _: OverflowPolicy) -> Result<Self, ArithmeticError> {
+        if *rhs == 0 {
+            return Err(ArithmeticError::DivisionByZero);
+        }
+        Ok(self.wrapping_rem_euclid(*rhs))
+    }
+
+    /// Negative exponents truncate toward zero like division does
+    fn power(&self, rhs: &Self, overflow: OverflowPolicy) -> Result<Self, ArithmeticError> {
+        match u32::try_from(*rhs) {
+            Ok(exponent) => with_policy(overflow, || self.checked_pow(exponent), || self.wrapping_pow(exponent), || self.saturating_pow(exponent)),
+            Err(_) => match self {
+                0 => Err(ArithmeticError::DivisionByZero),
+                1 => Ok(1),
+                -1 => Ok(if rhs % 2 == 0 { 1 } else { -1 }),
+                _ => Ok(0),
+            },
+        }
+    }
+
+    fn factorial(&self, overflow: OverflowPolicy) -> Result<Self, ArithmeticError> {
+        if *self < 0 {
+            return Err(ArithmeticError::InvalidOperand);
+        }
+        let mut result: i32 = 1;
+        for factor in 2..=*self {
+            result = result.multiply(&factor, overflow)?;
+            if result == 0 || result == i32::MAX {
+                // wrapped to zero or saturated, no further factor changes the result
+                break;
+            }
+        }
+        Ok(result)
+    }
+
+    fn abs(&self, overflow: OverflowPolicy) -> Result<Self, ArithmeticError> {
+        with_policy(overflow, || self.checked_abs(), || self.wrapping_abs(), || self.saturating_abs())
+    }
+
+    fn bit_and(&self, rhs: &Self, _: OverflowPolicy) -> Result<Self, ArithmeticError> {
+        Ok(self & rhs)
+    }
+
+    fn bit_or(&self, rhs: &Self, _: OverflowPolicy) -> Result<Self, ArithmeticError> {
+        Ok(self | rhs)
+    }
+
+    fn bit_xor(&self, rhs: &Self, _: OverflowPolicy) -> Result<Self, ArithmeticError> {
+        Ok(self ^ rhs)
+    }
+
+    fn bit_not(&self, _: OverflowPolicy) -> Result<Self, ArithmeticError> {
+        Ok(!self)
+    }
+
+    /// Shifting by 32 or more overflows, wrapping only uses the lowest five bits of the amount
+    /// and saturating shifts out every bit
+    fn shift_left(&self, rhs: &Self, overflow: OverflowPolicy) -> Result<Self, ArithmeticError> {
+        let amount = u32::try_from(*rhs).map_err(|_| ArithmeticError::InvalidOperand)?;
+        with_policy(overflow, || self.checked_shl(amount), || self.wrapping_shl(amount), || self.checked_shl(amount).unwrap_or(0))
+    }
+
+    /// Shifting by 32 or more overflows, wrapping only uses the lowest five bits of the amount
+    /// and saturating shifts out every bit but the sign
+    fn shift_right(&self, rhs: &Self, overflow: OverflowPolicy) -> Result<Self, ArithmeticError> {
+        let amount = u32::try_from(*rhs).map_err(|_| ArithmeticError::InvalidOperand)?;
+        with_policy(overflow, || self.checked_shr(amount), || self.wrapping_shr(amount), || self.checked_shr(amount).unwrap_or(self >> 31))
+    }
+
+    /// Negative values are shown as their two's complement bits, like they are written
+    fn display_radix(&self, radix: u32) -> Option<String> {
+        let bits = *self as u32;
+        match radix {
+            2 => Some(format!("0b{:b}", bits)),
+            8 => Some(format!("0o{:o}", bits)),
+            16 => Some(format!("0x{:X}", bits)),
+            _ => None,
+        }
+    }
+
+    fn sqrt(&self) -> Result<Self, ArithmeticError> {
+        if *self < 0 {
+            return Err(ArithmeticError::InvalidOperand);
+        }
+        Ok(self.isqrt())
+    }
+}
+
+/// IEEE 754 arithmetic, overflow results in infinity regardless of the policy
+impl Value for f64 {
+    fn parse_literal(literal: &str) -> Option<Self> {
+        match literal_digits(literal)? {
+            (10, digits) => digits.parse().ok(),
+            (radix, digits) => BigInt::parse_bytes(digits.as_bytes(), radix).map(|value| Value::to_f64(&value)),
+        }
+    }
+
+    fn parse_saved(text: &str) -> Option<Self> {
+        text.parse().ok()
+    }
+
+    fn from_i32(value: i32) -> Self {
+        f64::from(value)
+    }
+
+    fn from_f64(value: f64) -> Option<Self> {
+        Some(value)
+    }
+
+    fn to_f64(&self) -> f64 {
+        *self
+    }
+
+    fn is_integer(&self) -> bool {
+        self.fract() == 0.0
+    }
+
+    fn negate(&self, _: OverflowPolicy) -> Result<Self, ArithmeticError> {
+        Ok(-self)
+    }
+
+    fn add(&self, rhs: &Self, _: OverflowPolicy) -> Result<Self, ArithmeticError> {
+        Ok(self + rhs)
+    }
+
+    fn subtract(&self, rhs: &Self, _: OverflowPolicy) -> Result<Self, ArithmeticError> {
+        Ok(self - rhs)
+    }
+
+    fn multiply(&self, rhs: &Self, _: OverflowPolicy) -> Result<Self, ArithmeticError> {
+        Ok(self * rhs)
+    }
+
+    fn divide(&self, rhs: &Self, _: OverflowPolicy) -> Result<Self, ArithmeticError> {
+        if *rhs == 0.0 {
+            return Err(ArithmeticError::DivisionByZero);
+        }
+        Ok(self / rhs)
+    }
+
+    fn floor_divide(&self, rhs: &Self, overflow: OverflowPolicy) -> Result<Self, ArithmeticError> {
+        self.divide(rhs, overflow).map(f64::floor)
+    }
+
+    fn remainder(&self, rhs: &Self, _: OverflowPolicy) -> Result<Self, ArithmeticError> {
+        if *rhs == 0.0 {
+            return Err(ArithmeticError::DivisionByZero);
+        }
+        Ok(self % rhs)
+    }
+
+    fn remainder_euclid(&self, rhs: &Self, _: OverflowPolicy) -> Result<Self, ArithmeticError> {
+        if *rhs == 0.0 {
+            return Err(ArithmeticError::DivisionByZero);
+        }
+        Ok(self.rem_euclid(*rhs))
+    }
+
+    fn power(&self, rhs: &Self, _: OverflowPolicy) -> Result<Self, ArithmeticError> {
+        Ok(self.powf(*rhs))
+    }
+
+    fn factorial(&self, _: OverflowPolicy) -> Result<Self, ArithmeticError> {
+        if *self < 0.0 || self.fract() != 0.0 {
+            return Err(ArithmeticError::InvalidOperand);
+        }
+        let mut result: f64 = 1.0;
+        let mut factor = 2.0;
+        while factor <= *self && result.is_finite() {
+            result *= factor;
+            factor += 1.0;
+        }
+        Ok(result)
+    }
+
+    fn abs(&self, _: OverflowPolicy) -> Result<Self, ArithmeticError> {
+        Ok(f64::abs(*self))
+    }
+
+    fn sqrt(&self) -> Result<Self, ArithmeticError> {
+        if *self < 0.0 {
+            return Err(ArithmeticError::InvalidOperand);
+        }
+        Ok(f64::sqrt(*self))
+    }
+
+    fn floor(&self) -> Self {
+        f64::floor(*self)
+    }
+
+    fn ceil(&self) -> Self {
+        f64::ceil(*self)
+    }
+
+    fn round(&self) -> Self {
+        f64::round(*self)
+    }
+}
+
+/// Arbitrary precision integer arithmetic that never overflows, division truncates toward zero
+impl Value for BigInt {
+    fn parse_literal(literal: &str) -> Option<Self> {
+        let (radix, digits) = literal_digits(literal)?;
+        BigInt::parse_bytes(digits.as_bytes(), radix)
+    }
+
+    fn parse_saved(text: &str) -> Option<Self> {
+        text.parse().ok()
+    }
+
+    fn from_i32(value: i32) -> Self {
+        BigInt::from(value)
+    }
+
+    fn from_f64(value: f64) -> Option<Self> {
+        FromPrimitive::from_f64(value.trunc())
+    }
+
+    fn to_f64(&self) -> f64 {
+        ToPrimitive::to_f64(self).unwrap_or(f64::NAN)
+    }
+
+    fn is_integer(&self) -> bool {
+        true
+    }
+
+    fn negate(&self, _: OverflowPolicy) -> Result<Self, ArithmeticError> {
+        Ok(-self)
+    }
+
+    fn add(&self, rhs: &Self, _: OverflowPolicy) -> Result<Self, ArithmeticError> {
+        Ok(self + rhs)
+    }
+
+    fn subtract(&self, rhs: &Self, _: OverflowPolicy) -> Result<Self, ArithmeticError> {
+        Ok(self - rhs)
+    }
+
+    fn multiply(&self, rhs: &Self, _: OverflowPolicy) -> Result<Self, ArithmeticError> {
+        Ok(self * rhs)
+    }
+
+    fn divide(&self, rhs: &Self, _: OverflowPolicy) -> Result<Self, ArithmeticError> {
+        if rhs.is_zero() {
+            return Err(ArithmeticError::DivisionByZero);
+        }
+        Ok(self / rhs)
+    }
+
+    fn floor_divide(&self, rhs: &Self, overflow: OverflowPolicy) -> Result<Self, ArithmeticError> {
+        let quotient = self.divide(rhs, overflow)?;
+        if !(self % rhs).is_zero() && self.is_negative() != rhs.is_negative() {
+            Ok(quotient - 1)
+        } else {
+            Ok(quotient)
+        }
+    }
+
+    fn remainder(&self, rhs: &Self, _: OverflowPolicy) -> Result<Self, ArithmeticError> {
+        if rhs.is_zero() {
+            return Err(ArithmeticError::DivisionByZero);
+        }
+        Ok(self % rhs)
+    }
+
+    fn remainder_euclid(&self, rhs: &Self, _: OverflowPolicy) -> Result<Self, ArithmeticError> {
+        if rhs.is_zero() {
+            return Err(ArithmeticError::DivisionByZero);
+        }
+        Ok(num_traits::Euclid::rem_euclid(self, rhs))
+    }
+
+    /// Negative exponents truncate toward zero like division does
+    fn power(&self, rhs: &Self, _: OverflowPolicy) -> Result<Self, ArithmeticError> {
+        // powers of 0, 1 and -1 stay within them for any exponent
+        let bounded = || if rhs.bit(0) { self.clone() } else { Signed::abs(self) };
+        if rhs.is_negative() {
+            return if self.is_zero() {
+                Err(ArithmeticError::DivisionByZero)
+            } else if Signed::abs(self).is_one() {
+                Ok(bounded())
+            } else {
+                Ok(BigInt::zero())
+            };
+        }
+        match u32::try_from(rhs) {
+            Ok(exponent) => Ok(self.pow(exponent)),
+            Err(_) if self.is_zero() || Signed::abs(self).is_one() => Ok(bounded()),
+            Err(_) => Err(ArithmeticError::Overflow),
+        }
+    }
+
+    fn bit_and(&self, rhs: &Self, _: OverflowPolicy) -> Result<Self, ArithmeticError> {
+        Ok(self & rhs)
+    }
+
+    fn bit_or(&self, rhs: &Self, _: OverflowPolicy) -> Result<Self, ArithmeticError> {
+        Ok(self | rhs)
+    }
+
+    fn bit_xor(&self, rhs: &Self, _: OverflowPolicy) -> Result<Self, ArithmeticError> {
+        Ok(self ^ rhs)
+    }
+
+    fn bit_not(&self, _: OverflowPolicy) -> Result<Self, ArithmeticError> {
+        Ok(!self)
+    }
+
+    fn shift_left(&self, rhs: &Self, _: OverflowPolicy) -> Result<Self, ArithmeticError> {
+        if rhs.is_negative() {
+            return Err(ArithmeticError::InvalidOperand);
+        }
+        match u32::try_from(rhs) {
+            Ok(amount) => Ok(self << amount),
+            Err(_) if self.is_zero() => Ok(BigInt::zero()),
+            Err(_) => Err(ArithmeticError::Overflow),
+        }
+    }
+
+    fn shift_right(&self, rhs: &Self, _: OverflowPolicy) -> Result<Self, ArithmeticError> {
+        if rhs.is_negative() {
+            return Err(ArithmeticError::InvalidOperand);
+        }
+        match u32::try_from(rhs) {
+            Ok(amount) => Ok(self >> amount),
+            Err(_) if self.is_negative() => Ok(-BigInt::one()),
+            Err(_) => Ok(BigInt::zero()),
+        }
+    }
+
+    fn display_radix(&self, radix: u32) -> Option<String> {
+        let prefix = match radix {
+            2 => "0b",
+            8 => "0o",
+            16 => "0x",
+            _ => return None,
+        };
+        let sign = if self.is_negative() { "-" } else { "" };
+        Some(format!("{}{}{}", sign, prefix, self.magnitude().to_str_radix(radix).to_uppercase()))
+    }
+
+    fn factorial(&self, _: OverflowPolicy) -> Result<Self, ArithmeticError> {
+        if self.is_negative() {
+            return Err(ArithmeticError::InvalidOperand);
+        }
+        let mut result = BigInt::one();
+        let mut factor = BigInt::from(2);
+        while &factor <= self {
+            result *= &factor;
+            factor += 1;
+        }
+        Ok(result)
+    }
+
+    fn abs(&self, _: OverflowPolicy) -> Result<Self, ArithmeticError> {
+        Ok(Signed::abs(self))
+    }
+
+    fn sqrt(&self) -> Result<Self, ArithmeticError> {
+        if self.is_negative() {
+            return Err(ArithmeticError::InvalidOperand);
+        }
+        Ok(BigInt::sqrt(self))
+    }
+}
+
+/// Exact arithmetic on fractions that are always kept in lowest terms
+impl Value for BigRational {
+    /// Decimal literals are converted exactly, so `0.1` is `1/10`
+    fn parse_literal(literal: &str) -> Option<Self> {
+        let literal = match literal_digits(literal)? {
+            (10, digits) => digits,
+            (radix, digits) => return BigInt::parse_bytes(digits.as_bytes(), radix).map(BigRational::from_integer),
+        };
+        let (mantissa, exponent) = match literal.find(['e', 'E']) {
+            Some(index) => (&literal[..index], literal[index + 1..].parse::<i32>().ok()?),
+            None => (literal.as_str(), 0),
+        };
+        let (integer, fraction) = mantissa.split_once('.').unwrap_or((mantissa, ""));
+        if integer.is_empty() && fraction.is_empty() {
+            return None;
+        }
+        let digits = format!("{}{}", integer, fraction);
+        if !digits.bytes().all(|c| c.is_ascii_digit()) {
+            return None;
+        }
+        let numerator = BigInt::from_str(&digits).ok()?;
+        let ten = BigRational::from_integer(BigInt::from(10));
+        let exponent = exponent.checked_sub(i32::try_from(fraction.len()).ok()?)?;
+        Some(BigRational::from_integer(numerator) * ten.pow(exponent))
+    }
+
+    fn parse_saved(text: &str) -> Option<Self> {
+        text.parse().ok()
+    }
+
+    fn from_i32(value: i32) -> Self {
+        BigRational::from_integer(BigInt::from(value))
+    }
+
+    /// The exact binary value of the float, so `0.1` is not `1/10`
+    fn from_f64(value: f64) -> Option<Self> {
+        BigRational::from_float(value)
+    }
+
+    fn to_f64(&self) -> f64 {
+        ToPrimitive::to_f64(self).unwrap_or(f64::NAN)
+    }
+
+    fn is_integer(&self) -> bool {
+        BigRational::is_integer(self)
+    }
+
+    fn negate(&self, _: OverflowPolicy) -> Result<Self, ArithmeticError> {
+        Ok(-self)
+    }
+
+    fn add(&self, rhs: &Self, _: OverflowPolicy) -> Result<Self, ArithmeticError> {
+        Ok(self + rhs)
+    }
+
+    fn subtract(&self, rhs: &Self, _: OverflowPolicy) -> Result<Self, ArithmeticError> {
+        Ok(self - rhs)
+    }
+
+    fn multiply(&self, rhs: &Self, _: OverflowPolicy) -> Result<Self, ArithmeticError> {
+        Ok(self * rhs)
+    }
+
+    fn divide(&self, rhs: &Self, _: OverflowPolicy) -> Result<Self, ArithmeticError> {
+        if rhs.is_zero() {
+            return Err(ArithmeticError::DivisionByZero);
+        }
+        Ok(self / rhs)
+    }
+
+    fn floor_divide(&self, rhs: &Self, overflow: OverflowPolicy) -> Result<Self, ArithmeticError> {
+        self.divide(rhs, overflow).map(|quotient| quotient.floor())
+    }
+
+    fn remainder(&self, rhs: &Self, overflow: OverflowPolicy) -> Result<Self, ArithmeticError> {
+        let quotient = self.divide(rhs, overflow)?.trunc();
+        Ok(self - rhs * quotient)
+    }
+
+    fn remainder_euclid(&self, rhs: &Self, overflow: OverflowPolicy) -> Result<Self, ArithmeticError> {
+        let divisor = Signed::abs(rhs);
+        let quotient = self.divide(&divisor, overflow)?.floor();
+        Ok(self - divisor * quotient)
+    }
+
+    /// Only integer exponents keep the result rational
+    fn power(&self, rhs: &Self, _: OverflowPolicy) -> Result<Self, ArithmeticError> {
+        if !rhs.is_integer() {
+            return Err(ArithmeticError::InvalidOperand);
+        }
+        if self.is_zero() && rhs.is_negative() {
+            return Err(ArithmeticError::DivisionByZero);
+        }
+        let exponent = i32::try_from(rhs.to_integer()).map_err(|_| ArithmeticError::Overflow)?;
+        Ok(self.pow(exponent))
+    }
+
+    fn factorial(&self, overflow: OverflowPolicy) -> Result<Self, ArithmeticError> {
+        if !self.is_integer() {
+            return Err(ArithmeticError::InvalidOperand);
+        }
+        self.to_integer().factorial(overflow).map(BigRational::from_integer)
+    }
+
+    fn abs(&self, _: OverflowPolicy) -> Result<Self, ArithmeticError> {
+        Ok(Signed::abs(self))
+    }
+
+    /// Exact if both numerator and denominator are perfect squares, approximated otherwise
+    fn sqrt(&self) -> Result<Self, ArithmeticError> {
+        if self.is_negative() {
+            return Err(ArithmeticError::InvalidOperand);
+        }
+        let numerator = self.numer().sqrt();
+        let denominator = self.denom().sqrt();
+        if &(&numerator * &numerator) == self.numer() && &(&denominator * &denominator) == self.denom() {
+            return Ok(BigRational::new(numerator, denominator));
+        }
+        BigRational::from_float(f64::sqrt(Value::to_f64(self))).ok_or(ArithmeticError::Overflow)
+    }
+
+    fn floor(&self) -> Self {
+        BigRational::floor(self)
+    }
+
+    fn ceil(&self) -> Self {
+        BigRational::ceil(self)
+    }
+
+    fn round(&self) -> Self {
+        BigRational::round(self)
+    }
+
+    fn display_as(&self, style: FractionStyle) -> String {
+        match style {
+            FractionStyle::Fraction => self.to_string(),
+            FractionStyle::Mixed => {
+                let whole = self.trunc();
+                let rest = Signed::abs(&(self - &whole));
+                if whole.is_zero() || rest.is_zero() {
+                    self.to_string()
+                } else {
+                    format!("{} {}", whole, rest)
+                }
+            }
+            FractionStyle::Decimal(digits) => {
+                let scale = num_traits::pow(BigInt::from(10), digits);
+                let scaled = (Signed::abs(self) * BigRational::from_integer(scale.clone())).round().to_integer();
+                let sign = if self.is_negative() && !scaled.is_zero() { "-" } else { "" };
+                let integer = &scaled / &scale;
+                let fraction = format!("{:0>width$}", (&scaled % &scale).to_string(), width = digits);
+                let fraction = fraction.trim_end_matches('0');
+                if fraction.is_empty() {
+                    format!("{}{}", sign, integer)
+                } else {
+                    format!("{}{}.{}", sign, integer, fraction)
+                }
+            }
+        }
+    }
+}